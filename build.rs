@@ -0,0 +1,21 @@
+use std::env;
+use std::process::Command;
+
+// the commit is only known when building from a checkout, a source archive is built without it
+fn main() {
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(sha) = sha {
+        println!("cargo:rustc-env=YKTOTP_GIT_SHA={}", sha.trim());
+    }
+    println!(
+        "cargo:rustc-env=YKTOTP_BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -1,187 +1,5263 @@
+use std::env;
+use std::fmt;
 use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
+use crate::base32;
+use crate::cache;
+use crate::hex;
+use crate::log;
+use crate::migration;
 use crate::oath;
+use crate::otpauth;
 use crate::time;
-use crate::yubikey;
+use crate::yubikey::{self, SmartCard};
+
+const STEAM_ALPHABET: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+const STEAM_DIGITS: usize = 5;
+
+const DEFAULT_TOUCH_TIMEOUT: Duration = Duration::from_secs(15);
+// services tolerate a step or two of clock skew, farther steps are of no use
+const MAX_STEP_OFFSET: i64 = 10;
+const DEFAULT_VERIFY_WINDOW: u32 = 1;
+const DEFAULT_DIGITS: u8 = 6;
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(1);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_LISTING_TTL: Duration = Duration::from_secs(2);
+// requests are tiny, so the limit Chrome enforces for messages to the browser is used
+// for both directions
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+// details of an error, e.g. from serde, may quote arbitrary parts of the request
+const MAX_ERROR_MESSAGE_LENGTH: usize = 200;
+
+const PROTOCOL_VERSION: u32 = 1;
+const FEATURES: &[&str] = &[
+    "add_credential",
+    "delete_credential",
+    "hotp",
+    "code_all",
+    "steam",
+    "touch",
+    "password",
+    "serial",
+    "transport",
+    "set_password",
+    "verify",
+    "capacity",
+    "require_touch",
+    "oath_info",
+    "challenge_response",
+    "set_touch",
+    "import_migration",
+    "code_by_issuer",
+    "credential_info",
+    "touch_required_list",
+    "code_from_secret",
+    "hotp_at",
+    "host_info",
+    "latency",
+];
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum Request {
+    /// Only credentials matching the `query` are listed, if one is given. Without `sort`, the
+    /// names keep the order of the device.
+    AccountList {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        query: Option<String>,
+        #[serde(default)]
+        sort: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        oath_type: Option<oath::OathType>,
+    },
+    /// The code is calculated for the current time unless a `timestamp` is given. Without an
+    /// `account`, the one configured as default is used.
+    Code {
+        #[serde(default)]
+        account: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+        #[serde(default = "case_insensitive_default")]
+        case_insensitive: bool,
+        #[serde(default)]
+        next: bool,
+        #[serde(default)]
+        include_timestamp: bool,
+        #[serde(default)]
+        stream: bool,
+        /// Shifts the calculation by whole periods, within `MAX_STEP_OFFSET` in either direction.
+        #[serde(default)]
+        step_offset: i64,
+        /// The clock of the caller in seconds since the Unix epoch, answered with its skew.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_time: Option<u64>,
+    },
+    CodeExact {
+        account: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+        #[serde(default)]
+        next: bool,
+        #[serde(default)]
+        include_timestamp: bool,
+        #[serde(default)]
+        stream: bool,
+    },
+    CodeAll,
+    /// Like `CodeAll`, for the credentials whose issuer matches `issuer` exactly, ignoring case.
+    CodeByIssuer {
+        issuer: String,
+    },
+    /// Calculates the codes of several accounts, each failing on its own.
+    CodeMany {
+        accounts: Vec<String>,
+    },
+    /// Returns the credential a `Code` request would match without calculating a code.
+    Resolve {
+        #[serde(default)]
+        account: String,
+    },
+    /// The `code` is accepted up to `window` periods before or after the current one.
+    Verify {
+        account: String,
+        code: String,
+        #[serde(default = "verify_window_default")]
+        window: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Succeeds once the YubiKey is touched, using any credential that requires a touch.
+    RequireTouch,
+    /// Every request advances the counter stored on the YubiKey.
+    HotpCode {
+        account: String,
+    },
+    /// Calculates a TOTP code in software, neither storing the secret nor accessing the YubiKey.
+    CodeFromSecret {
+        secret_base32: String,
+        #[serde(default = "algorithm_default")]
+        algorithm: oath::Algorithm,
+        #[serde(default = "digits_default")]
+        digits: u8,
+        #[serde(default = "period_default")]
+        period: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Calculates the HOTP code at `counter` in software, so neither counter is advanced.
+    HotpAt {
+        secret_base32: String,
+        counter: u64,
+        #[serde(default = "algorithm_default")]
+        algorithm: oath::Algorithm,
+        #[serde(default = "digits_default")]
+        digits: u8,
+    },
+    AddCredential(oath::CredentialDefinition),
+    Import {
+        uris: Vec<String>,
+    },
+    /// The `data` of a Google Authenticator export, with or without its `otpauth-migration://` URI.
+    ImportMigration {
+        data: String,
+    },
+    DeleteCredential {
+        account: String,
+    },
+    Exists {
+        account: String,
+    },
+    RenameCredential {
+        from: String,
+        to: String,
+    },
+    /// Refused with `unsupported` before anything is sent, as no YubiKey changes the touch policy
+    /// of a stored credential.
+    SetTouch {
+        account: String,
+        require_touch: bool,
+    },
+    /// The `password` of the message is the current one, as it unlocks the application first.
+    SetPassword {
+        new_password: Option<String>,
+    },
+    /// Sends the challenge to the HMAC-SHA1 secret of the `slot` of the OTP application.
+    ChallengeResponse {
+        slot: u8,
+        challenge_hex: String,
+    },
+    DeviceInfo,
+    /// The version of the OATH application, which is updated independently of the firmware.
+    OathInfo,
+    ListDevices,
+    CredentialList,
+    /// Returns what `CredentialList` does for the single credential named `account` exactly.
+    CredentialInfo {
+        account: String,
+    },
+    /// Answered with an `AccountList` of the credentials requiring a touch.
+    TouchRequiredList,
+    /// `max` is only known for the firmware versions supported by the OATH application.
+    Capacity,
+    /// Times selecting the OATH application once more, which neither reads nor changes credentials.
+    Latency,
+    Export,
+    Version,
+    /// The build of the executable, for bug reports.
+    HostInfo,
+    Ping,
+    /// Answers the `payload` unchanged without accessing the YubiKey, to debug the framing.
+    Echo {
+        payload: String,
+    },
+    /// Ends the session once it is answered, like closing the input would.
+    Close,
+    /// Irreversibly removes all credentials, so it has to be confirmed.
+    Reset {
+        #[serde(default)]
+        confirm: bool,
+    },
+}
+
+// the types named in the error for an unknown type and the kind of every request are generated
+// from one list, whose match fails to compile if a variant is missing
+macro_rules! request_types {
+    ($($listed:ident,)* ; $($hidden:ident,)*) => {
+        // named in the error for an unknown type, as many as fit into the bounded message
+        const REQUEST_TYPES: &[&str] = &[$(stringify!($listed)),*];
+        // accepted, but neither advertised as a feature nor named in the error for an unknown type
+        const HIDDEN_REQUEST_TYPES: &[&str] = &[$(stringify!($hidden)),*];
+
+        impl Request {
+            // only the type is logged, requests may carry secrets
+            fn kind(&self) -> &'static str {
+                match self {
+                    $(Request::$listed { .. } => stringify!($listed),)*
+                    $(Request::$hidden { .. } => stringify!($hidden),)*
+                }
+            }
+        }
+    };
+}
+
+request_types! {
     AccountList,
-    Code { account: String },
+    Code,
+    CodeExact,
+    CodeAll,
+    CodeByIssuer,
+    CodeMany,
+    Resolve,
+    Verify,
+    RequireTouch,
+    HotpCode,
+    CodeFromSecret,
+    HotpAt,
+    AddCredential,
+    Import,
+    ImportMigration,
+    DeleteCredential,
+    Exists,
+    RenameCredential,
+    SetTouch,
+    SetPassword,
+    ChallengeResponse,
+    DeviceInfo,
+    OathInfo,
+    ListDevices,
+    CredentialList,
+    CredentialInfo,
+    TouchRequiredList,
+    Capacity,
+    Latency,
+    Export,
+    Version,
+    HostInfo,
+    Ping,
+    Close,
+    Reset,
+    ;
+    Echo,
+}
+
+impl Request {
+    // a cached listing would be outdated afterwards, whether the request succeeded or not
+    fn modifies_credentials(&self) -> bool {
+        matches!(
+            self,
+            Request::AddCredential(_)
+                | Request::Import { .. }
+                | Request::ImportMigration { .. }
+                | Request::DeleteCredential { .. }
+                | Request::RenameCredential { .. }
+                | Request::SetPassword { .. }
+                | Request::Reset { .. }
+        )
+    }
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+fn case_insensitive_default() -> bool {
+    oath::MatchOptions::default().case_insensitive
+}
+
+fn verify_window_default() -> u32 {
+    DEFAULT_VERIFY_WINDOW
+}
+
+fn algorithm_default() -> oath::Algorithm {
+    oath::Algorithm::Sha1
+}
+
+fn digits_default() -> u8 {
+    DEFAULT_DIGITS
+}
+
+fn period_default() -> u64 {
+    oath::DEFAULT_PERIOD
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Message {
+    #[serde(flatten)]
+    pub request: Request,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<u32>,
+    #[serde(default)]
+    pub transport: yubikey::Transport,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Wraps every response to the message, so clients can tell errors apart by `ok` alone.
+    #[serde(default)]
+    pub envelope: bool,
+}
+
+/// Byte order of the length prefix framing every message.
+///
+/// Chrome uses the native byte order, other clients may pin it to one explicitly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    #[default]
+    Native,
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "native" => Some(ByteOrder::Native),
+            "little" => Some(ByteOrder::LittleEndian),
+            "big" => Some(ByteOrder::BigEndian),
+            _ => None,
+        }
+    }
+
+    fn decode(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            ByteOrder::Native => u32::from_ne_bytes(bytes),
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn encode(self, length: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::Native => length.to_ne_bytes(),
+            ByteOrder::LittleEndian => length.to_le_bytes(),
+            ByteOrder::BigEndian => length.to_be_bytes(),
+        }
+    }
+}
+
+/// How messages are delimited on the streams the host is served over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    LengthPrefix(ByteOrder),
+    /// One JSON object per line, for tools that cannot produce a length prefix.
+    JsonLines,
+}
+
+impl Framing {
+    fn read(self, input: &mut impl BufRead) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Framing::LengthPrefix(byte_order) => read_input(input, byte_order),
+            Framing::JsonLines => read_json_line(input),
+        }
+    }
+
+    fn write(self, output: &mut impl Write, response: &impl Answer) -> Result<(), Error> {
+        match self {
+            Framing::LengthPrefix(byte_order) => write(output, response, byte_order),
+            Framing::JsonLines => write_line(output, response),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Input {
+    Single(Message),
+    Batch(Vec<Result<Message, Rejected>>),
+}
+
+// a message that is no valid request is still answered with its id, if it has a readable one
+#[derive(Debug)]
+struct Rejected {
+    id: Option<String>,
+    error: Error,
+}
+
+impl From<Error> for Rejected {
+    fn from(error: Error) -> Self {
+        Rejected { id: None, error }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountCode {
+    pub account: String,
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_for: Option<u64>,
+    pub touch_required: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Device {
+    pub serial: u32,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum Response {
-    Code { account: String, code: String },
-    AccountList { accounts: Vec<String> },
-    Error { error: String },
+    // before all other variants, as they would ignore the wrapped response
+    Enveloped {
+        ok: bool,
+        #[serde(with = "tagged")]
+        data: Box<Response>,
+    },
+    EnvelopedError {
+        ok: bool,
+        #[serde(with = "tagged")]
+        error: Box<Response>,
+    },
+    Code {
+        account: String,
+        code: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        valid_for: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_code: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_valid_for: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        clock_skew: Option<i64>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        warnings: Vec<String>,
+    },
+    CodeFromSecret {
+        code: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        valid_for: Option<u64>,
+    },
+    TouchRequired {
+        account: String,
+        touch_required: bool,
+    },
+    TouchWaiting {
+        account: String,
+        elapsed_ms: u64,
+    },
+    Ambiguous {
+        account: String,
+        candidates: Vec<String>,
+    },
+    Ok {
+        ok: bool,
+    },
+    Echo {
+        payload: String,
+    },
+    AccountList {
+        accounts: Vec<String>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        unparseable: usize,
+    },
+    CodeAll {
+        codes: Vec<AccountCode>,
+    },
+    CodeMany {
+        codes: Vec<Response>,
+    },
+    CredentialList {
+        credentials: Vec<oath::Credential>,
+    },
+    CredentialInfo(oath::Credential),
+    Export {
+        credentials: Vec<String>,
+    },
+    Capacity {
+        used: usize,
+        max: Option<usize>,
+    },
+    Latency {
+        millis: u64,
+    },
+    ImportResult {
+        results: Vec<ImportItem>,
+    },
+    MigrationResult {
+        results: Vec<MigrationItem>,
+    },
+    DeviceInfo {
+        serial: u32,
+        version: String,
+    },
+    DeviceList {
+        devices: Vec<Device>,
+    },
+    OathInfo {
+        version: Option<String>,
+        oath_locked: bool,
+    },
+    Hmac {
+        response_hex: String,
+    },
+    Version {
+        protocol: u32,
+        features: Vec<String>,
+    },
+    HostInfo {
+        version: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        git_sha: Option<String>,
+        build_profile: String,
+    },
+    Error {
+        kind: String,
+        message: String,
+    },
+    Verify {
+        account: String,
+        valid: bool,
+    },
+    // before `Resolved`, which would ignore whether the credential exists
+    Exists {
+        account: String,
+        exists: bool,
+    },
+    Resolved {
+        account: String,
+    },
+    Batch(Vec<Reply>),
+}
+
+impl Response {
+    // names the variant in the `type` of an enveloped response
+    fn kind(&self) -> &'static str {
+        match self {
+            Response::Enveloped { .. } => "Enveloped",
+            Response::EnvelopedError { .. } => "EnvelopedError",
+            Response::Code { .. } => "Code",
+            Response::CodeFromSecret { .. } => "CodeFromSecret",
+            Response::TouchRequired { .. } => "TouchRequired",
+            Response::TouchWaiting { .. } => "TouchWaiting",
+            Response::Ambiguous { .. } => "Ambiguous",
+            Response::Ok { .. } => "Ok",
+            Response::Echo { .. } => "Echo",
+            Response::AccountList { .. } => "AccountList",
+            Response::CodeAll { .. } => "CodeAll",
+            Response::CodeMany { .. } => "CodeMany",
+            Response::CredentialList { .. } => "CredentialList",
+            Response::CredentialInfo(_) => "CredentialInfo",
+            Response::Export { .. } => "Export",
+            Response::Capacity { .. } => "Capacity",
+            Response::Latency { .. } => "Latency",
+            Response::ImportResult { .. } => "ImportResult",
+            Response::MigrationResult { .. } => "MigrationResult",
+            Response::DeviceInfo { .. } => "DeviceInfo",
+            Response::DeviceList { .. } => "DeviceList",
+            Response::OathInfo { .. } => "OathInfo",
+            Response::Hmac { .. } => "Hmac",
+            Response::Version { .. } => "Version",
+            Response::HostInfo { .. } => "HostInfo",
+            Response::Error { .. } => "Error",
+            Response::Verify { .. } => "Verify",
+            Response::Resolved { .. } => "Resolved",
+            Response::Exists { .. } => "Exists",
+            Response::Batch(_) => "Batch",
+        }
+    }
+}
+
+// a bare response is read by guessing its variant from the fields, which the `type` of an
+// enveloped one makes unambiguous without breaking clients that do not ask for an envelope
+mod tagged {
+    use serde::de::{self, Deserializer};
+    use serde::ser::Serializer;
+    use serde::{Deserialize, Serialize};
+
+    use super::Response;
+
+    #[derive(Serialize)]
+    struct Tagged<'a> {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        #[serde(flatten)]
+        response: &'a Response,
+    }
+
+    pub fn serialize<S: Serializer>(response: &Response, serializer: S) -> Result<S::Ok, S::Error> {
+        Tagged {
+            kind: response.kind(),
+            response,
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<Response>, D::Error> {
+        let mut value = serde_json::Value::deserialize(deserializer)?;
+        let kind = match value
+            .as_object_mut()
+            .and_then(|fields| fields.remove("type"))
+        {
+            Some(serde_json::Value::String(kind)) => kind,
+            _ => return Err(de::Error::missing_field("type")),
+        };
+        let response = Response::deserialize(value).map_err(de::Error::custom)?;
+        if response.kind() != kind {
+            return Err(de::Error::custom(format!("invalid {} response", kind)));
+        }
+        Ok(Box::new(response))
+    }
+}
+
+// the outcome of importing a single URI, so a partially failed import can be retried
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ImportItem {
+    pub uri: String,
+    #[serde(flatten)]
+    pub response: Response,
+}
+
+// the outcome of a single credential of a migration payload, named as it would be stored
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MigrationItem {
+    pub account: String,
+    #[serde(flatten)]
+    pub response: Response,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub response: Response,
 }
 
 #[derive(Debug)]
 pub enum Error {
     Read,
+    BadJson(String),
+    UnknownRequestType(String),
+    InvalidUtf8(usize),
     Write,
+    ResponseTooLarge,
+    Unconfirmed,
+    EmptySearchTerm,
+    StepOffsetOutOfRange,
+    WindowOutOfRange,
+    InvalidSlot,
+    InvalidChallenge,
+    InvalidCodeParameters,
+    Timeout,
     Yubikey(yubikey::Error),
     Oath(oath::Error),
+    Uri(otpauth::Error),
+    Migration(migration::Error),
+}
+
+pub fn handle_request(message: &Message, notify: &mut dyn FnMut(&Response)) -> Response {
+    handle_request_cached(message, notify, &ListingCache::new(Duration::ZERO))
+}
+
+fn handle_request_cached(
+    message: &Message,
+    notify: &mut dyn FnMut(&Response),
+    cache: &ListingCache,
+) -> Response {
+    let password = message.password.clone();
+    let serial = message.serial;
+    let transport = message.transport;
+    let connect = move || open_yubikey(password.as_deref(), serial, transport);
+    let response = process(message, connect, notify, cache);
+    invalidate_listing(cache, &message.request);
+    response
+}
+
+fn invalidate_listing(cache: &ListingCache, request: &Request) {
+    if request.modifies_credentials() {
+        cache.clear();
+    }
 }
 
-pub fn handle_request(request: &Request) -> Response {
-    match request {
-        Request::Code { account } => read_otp(&account),
-        Request::AccountList => read_accounts_list(),
+// every outcome is a response, whether the request is refused before the YubiKey is accessed
+// or the device fails; only a request that needs it calls `connect`
+fn process<Y: yubikey::SmartCard>(
+    message: &Message,
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    notify: &mut dyn FnMut(&Response),
+    cache: &ListingCache,
+) -> Response {
+    match &message.request {
+        Request::Code {
+            account,
+            timestamp,
+            case_insensitive,
+            next,
+            include_timestamp,
+            stream,
+            step_offset,
+            client_time,
+        } => read_otp(
+            connect,
+            with_default_account(account, default_account().as_deref()),
+            Matching::Fuzzy(oath::MatchOptions {
+                case_insensitive: *case_insensitive,
+            }),
+            CodeOptions {
+                timestamp: timestamp.unwrap_or_else(time::get_time),
+                next: *next,
+                include_timestamp: *include_timestamp,
+                stream: *stream,
+                step_offset: *step_offset,
+                client_time: *client_time,
+            },
+            timeouts(),
+            cache.of(DeviceKey::of(message)),
+            notify,
+        ),
+        Request::CodeExact {
+            account,
+            timestamp,
+            next,
+            include_timestamp,
+            stream,
+        } => read_otp(
+            connect,
+            account,
+            Matching::Exact,
+            CodeOptions {
+                timestamp: timestamp.unwrap_or_else(time::get_time),
+                next: *next,
+                include_timestamp: *include_timestamp,
+                stream: *stream,
+                step_offset: 0,
+                client_time: None,
+            },
+            timeouts(),
+            cache.of(DeviceKey::of(message)),
+            notify,
+        ),
+        Request::CodeAll => read_all_otps(connect, time::get_time()),
+        Request::CodeByIssuer { issuer } => read_issuer_otps(connect, issuer, time::get_time()),
+        Request::CodeMany { accounts } => read_many_otps(
+            connect,
+            accounts,
+            time::get_time(),
+            timeouts().request,
+            cache.of(DeviceKey::of(message)),
+        ),
+        Request::Resolve { account } => resolve(
+            connect,
+            with_default_account(account, default_account().as_deref()),
+            cache.of(DeviceKey::of(message)),
+        ),
+        Request::Verify {
+            account,
+            code,
+            window,
+            timestamp,
+        } => verify(
+            connect,
+            account,
+            code,
+            timestamp.unwrap_or_else(time::get_time),
+            *window,
+            timeouts(),
+            notify,
+        ),
+        Request::RequireTouch => require_touch(connect, time::get_time(), timeouts()),
+        Request::HotpCode { account } => read_hotp(connect, account),
+        Request::CodeFromSecret {
+            secret_base32,
+            algorithm,
+            digits,
+            period,
+            timestamp,
+        } => code_from_secret(
+            secret_base32,
+            *algorithm,
+            *digits,
+            *period,
+            timestamp.unwrap_or_else(time::get_time),
+        ),
+        Request::HotpAt {
+            secret_base32,
+            counter,
+            algorithm,
+            digits,
+        } => hotp_at(secret_base32, *algorithm, *digits, *counter),
+        Request::AccountList {
+            query,
+            sort,
+            oath_type,
+        } => {
+            let options = ListOptions {
+                query: query.clone(),
+                sort: *sort,
+                oath_type: *oath_type,
+            };
+            read_accounts_list(
+                connect,
+                options,
+                timeouts().request,
+                cache.of(DeviceKey::of(message)),
+            )
+        }
+        Request::AddCredential(definition) => add_credential(connect, definition),
+        Request::Import { uris } => import(connect, uris),
+        Request::ImportMigration { data } => import_migration(connect, data),
+        Request::DeleteCredential { account } => delete_credential(connect, account),
+        Request::Exists { account } => credential_exists(connect, account),
+        Request::RenameCredential { from, to } => rename_credential(connect, from, to),
+        Request::SetTouch { .. } => set_touch(connect),
+        Request::SetPassword { new_password } => set_password(connect, new_password.as_deref()),
+        Request::ChallengeResponse {
+            slot,
+            challenge_hex,
+        } => challenge_response(connect, *slot, challenge_hex),
+        Request::DeviceInfo => read_device_info(connect),
+        Request::OathInfo => read_oath_info(connect),
+        Request::ListDevices => read_device_list(),
+        Request::CredentialList => read_credential_list(connect),
+        Request::TouchRequiredList => read_touch_required_list(connect, time::get_time()),
+        Request::CredentialInfo { account } => {
+            read_credential_info(connect, account, time::get_time())
+        }
+        Request::Capacity => read_capacity(connect),
+        Request::Latency => measure_latency(connect, &oath_aid(), Instant::now),
+        Request::Export => export(connect),
+        Request::Version => version(),
+        Request::HostInfo => host_info(),
+        Request::Ping | Request::Close => Response::Ok { ok: true },
+        Request::Echo { payload } => Response::Echo {
+            payload: payload.clone(),
+        },
+        Request::Reset { confirm: false } => error_response(&Error::Unconfirmed),
+        Request::Reset { confirm: true } => reset(connect),
+    }
+}
+
+fn version() -> Response {
+    Response::Version {
+        protocol: PROTOCOL_VERSION,
+        features: FEATURES.iter().map(|f| f.to_string()).collect(),
+    }
+}
+
+// set by the build script, which only knows the commit when building from a checkout
+fn host_info() -> Response {
+    Response::HostInfo {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        git_sha: option_env!("YKTOTP_GIT_SHA").map(str::to_owned),
+        build_profile: env!("YKTOTP_BUILD_PROFILE").to_owned(),
+    }
+}
+
+fn open_yubikey(
+    password: Option<&str>,
+    serial: Option<u32>,
+    transport: yubikey::Transport,
+) -> Result<yubikey::Yubikey, Error> {
+    let aid = oath_aid();
+    let yubikey = match serial {
+        Some(serial) => yubikey::Yubikey::initialize_by_serial(serial, transport, &aid),
+        None => yubikey::Yubikey::initialize(transport, &aid),
     }
+    .map_err(Error::Yubikey)?;
+    if let Some(password) = password {
+        oath::unlock(&yubikey, yubikey.select_response(), password).map_err(Error::Oath)?;
+    }
+    Ok(yubikey)
 }
 
-fn read_accounts_list() -> Response {
-    let accounts = yubikey::Yubikey::initialize()
-        .map_err(Error::Yubikey)
-        .and_then(|y| oath::list_credentials(&y).map_err(Error::Oath));
+fn read_accounts_list<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    options: ListOptions,
+    timeout: Duration,
+    cache: DeviceCache,
+) -> Response {
+    let listing = match cache.listing() {
+        Some(listing) => Ok(listing),
+        None => with_timeout(timeout, move || {
+            let yubikey = connect()?;
+            oath::list_all_credentials(&yubikey).map_err(Error::Oath)
+        })
+        .inspect(|listing| cache.store_listing(listing)),
+    };
+    let accounts = listing.map(|listing| {
+        let listing = match options.oath_type {
+            Some(oath_type) => oath::filter_listing_by_type(listing, oath_type),
+            None => listing,
+        };
+        match options.query {
+            Some(query) => oath::filter_listing(listing, &query, oath::MatchOptions::default()),
+            None => listing,
+        }
+    });
 
     match accounts {
-        Ok(account_vec) => Response::AccountList {
-            accounts: account_vec,
+        Ok(listing) => Response::AccountList {
+            unparseable: listing.unparseable,
+            accounts: order_names(listing.names(), options.sort),
         },
-        Err(e) => Response::Error {
-            error: format!("{:?}", e),
+        Err(e) => error_response(&e),
+    }
+}
+
+// a name the device reports twice would show up twice in a picker, the first one is kept
+fn order_names(names: Vec<String>, sort: bool) -> Vec<String> {
+    let mut unique: Vec<String> = Vec::with_capacity(names.len());
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    if sort {
+        unique.sort_by_key(|name| name.to_lowercase());
+    }
+    unique
+}
+
+fn read_credential_list<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+) -> Response {
+    let timestamp = time::get_time();
+    let credentials =
+        connect().and_then(|y| oath::list_credentials_detailed(&y, timestamp).map_err(Error::Oath));
+
+    match credentials {
+        Ok(credentials) => Response::CredentialList { credentials },
+        Err(e) => error_response(&e),
+    }
+}
+
+// only the calculation of all codes tells which credentials require a touch
+fn read_touch_required_list<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    timestamp: u64,
+) -> Response {
+    let credentials =
+        connect().and_then(|y| oath::list_credentials_detailed(&y, timestamp).map_err(Error::Oath));
+
+    match credentials {
+        Ok(credentials) => Response::AccountList {
+            accounts: credentials
+                .into_iter()
+                .filter(|cred| cred.touch)
+                .map(|cred| cred.id)
+                .collect(),
+            unparseable: 0,
         },
+        Err(e) => error_response(&e),
     }
 }
 
-fn read_otp(search_term: &str) -> Response {
+fn read_credential_info<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    name: &str,
+    timestamp: u64,
+) -> Response {
+    let credential =
+        connect().and_then(|y| oath::credential_info(&y, name, timestamp).map_err(Error::Oath));
+
+    match credential {
+        Ok(credential) => Response::CredentialInfo(credential),
+        Err(e) => error_response(&e),
+    }
+}
+
+fn export<Y: yubikey::SmartCard>(connect: impl FnOnce() -> Result<Y, Error>) -> Response {
     let timestamp = time::get_time();
-    let code = yubikey::Yubikey::initialize()
-        .map_err(Error::Yubikey)
-        .and_then(|y| oath::calculate_fuzzy(&y, search_term, timestamp).map_err(Error::Oath));
+    let credentials =
+        connect().and_then(|y| oath::list_credentials_detailed(&y, timestamp).map_err(Error::Oath));
 
-    match code {
-        Ok(code) => Response::Code {
+    match credentials {
+        Ok(credentials) => Response::Export {
+            credentials: credentials.iter().map(otpauth::format).collect(),
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    request: Duration,
+    touch: Duration,
+    // the interval of the progress sent while waiting for a touch
+    heartbeat: Duration,
+}
+
+fn timeouts() -> Timeouts {
+    Timeouts {
+        request: timeout_from_env("YKTOTP_REQUEST_TIMEOUT", DEFAULT_REQUEST_TIMEOUT),
+        touch: timeout_from_env("YKTOTP_TOUCH_TIMEOUT", DEFAULT_TOUCH_TIMEOUT),
+        heartbeat: DEFAULT_HEARTBEAT,
+    }
+}
+
+// the listings of a session, so that repeated requests do not enumerate the credentials again;
+// codes are still always calculated on the device
+struct ListingCache {
+    // the names `AccountList` and `Resolve` need
+    listing: cache::Cache<DeviceKey, oath::Listing>,
+    // the code requests also need to know which credentials require a touch
+    credentials: cache::Cache<DeviceKey, Vec<oath::Credential>>,
+}
+
+impl ListingCache {
+    fn new(ttl: Duration) -> Self {
+        ListingCache {
+            listing: cache::Cache::new(ttl),
+            credentials: cache::Cache::new(ttl),
+        }
+    }
+
+    fn of(&self, key: DeviceKey) -> DeviceCache<'_> {
+        DeviceCache { cache: self, key }
+    }
+
+    fn clear(&self) {
+        self.listing.clear();
+        self.credentials.clear();
+    }
+}
+
+// the cached listings of the device a request is for; a hit does not extend the lifetime of a
+// listing, so a polling picker still refreshes
+struct DeviceCache<'a> {
+    cache: &'a ListingCache,
+    key: DeviceKey,
+}
+
+impl DeviceCache<'_> {
+    fn listing(&self) -> Option<oath::Listing> {
+        self.cache.listing.get(&self.key)
+    }
+
+    fn store_listing(&self, listing: &oath::Listing) {
+        self.cache.listing.insert(self.key.clone(), listing.clone())
+    }
+
+    fn credentials(&self) -> Option<Vec<oath::Credential>> {
+        self.cache.credentials.get(&self.key)
+    }
+
+    fn store_credentials(&self, creds: &[oath::Credential]) {
+        self.cache
+            .credentials
+            .insert(self.key.clone(), creds.to_vec())
+    }
+}
+
+// the cached credentials are matched if there are any, otherwise they are listed on the device
+fn credentials_of(
+    yubikey: &impl yubikey::SmartCard,
+    cached: Option<Vec<oath::Credential>>,
+    time: u64,
+) -> Result<Vec<oath::Credential>, Error> {
+    match cached {
+        Some(creds) => Ok(creds),
+        None => oath::list_credentials_detailed(yubikey, time).map_err(Error::Oath),
+    }
+}
+
+// the listing of one device is not answered for another, and a protected application only
+// for the password it was unlocked with, of which only a digest is kept
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct DeviceKey {
+    serial: Option<u32>,
+    transport: yubikey::Transport,
+    password: Option<Vec<u8>>,
+}
+
+impl DeviceKey {
+    fn of(message: &Message) -> Self {
+        DeviceKey {
+            serial: message.serial,
+            transport: message.transport,
+            password: message
+                .password
+                .as_ref()
+                .map(|password| Sha256::digest(password.as_bytes()).to_vec()),
+        }
+    }
+}
+
+fn timeout_from_env(variable: &str, default: Duration) -> Duration {
+    env::var(variable)
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .map_or(default, Duration::from_secs)
+}
+
+// PC/SC calls block, so a hanging device is only escaped by no longer waiting for the thread
+fn with_timeout<T: Send + 'static>(
+    timeout: Duration,
+    work: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(work());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::Timeout),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(Error::Oath(oath::Error::Yubikey)),
+    }
+}
+
+fn byte_order() -> ByteOrder {
+    env::var("YKTOTP_BYTE_ORDER")
+        .ok()
+        .and_then(|name| ByteOrder::parse(&name))
+        .unwrap_or_default()
+}
+
+// an emulated or otherwise non-standard OATH application may be registered under a different ID
+fn oath_aid() -> Vec<u8> {
+    env::var("YKTOTP_OATH_AID")
+        .ok()
+        .and_then(|aid| parse_aid(&aid))
+        .unwrap_or_else(|| yubikey::OATH_AID.to_vec())
+}
+
+// application IDs are between 5 and 16 bytes long
+fn parse_aid(aid: &str) -> Option<Vec<u8>> {
+    hex::decode(aid.trim()).filter(|aid| (5..=16).contains(&aid.len()))
+}
+
+fn read_otp<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    search_term: &str,
+    matching: Matching,
+    options: CodeOptions,
+    timeouts: Timeouts,
+    cache: DeviceCache,
+    notify: &mut dyn FnMut(&Response),
+) -> Response {
+    let search_term = &normalize_search_term(search_term, matching);
+    // an empty term would match every credential
+    if search_term.is_empty() {
+        return error_response(&Error::EmptySearchTerm);
+    }
+    if !(-MAX_STEP_OFFSET..=MAX_STEP_OFFSET).contains(&options.step_offset) {
+        return error_response(&Error::StepOffsetOutOfRange);
+    }
+    let timestamp = options.timestamp;
+    let codes = calculate_with_touch(
+        connect,
+        search_term,
+        matching,
+        options,
+        timeouts,
+        cache,
+        |touch| notify(&touch_progress(search_term, touch)),
+    );
+
+    match codes {
+        Ok((name, code, next_code)) => Response::Code {
+            account: search_term.to_owned(),
+            code: format_code(&code),
+            valid_for: code.period.map(|period| valid_for(timestamp, period)),
+            next_code: next_code.as_ref().map(format_code),
+            next_valid_for: next_code
+                .and(code.period)
+                .map(|period| valid_for(timestamp, period).saturating_add(period)),
+            // reveals the clock of the host to debug skew against the service
+            timestamp: options.include_timestamp.then_some(timestamp),
+            // positive if the clock of the caller is ahead of the host
+            clock_skew: options
+                .client_time
+                .map(|client_time| clock_skew(client_time, timestamp)),
+            warnings: match_warnings(&name, search_term, matching),
+        },
+        Err(Error::Oath(oath::Error::TooManyMatchingCredentials(candidates))) => {
+            Response::Ambiguous {
+                account: search_term.to_owned(),
+                candidates,
+            }
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+// the difference of two times may exceed a signed 64 bit integer, which is far off anyway
+fn clock_skew(client_time: u64, timestamp: u64) -> i64 {
+    let skew = i128::from(client_time) - i128::from(timestamp);
+    skew.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+}
+
+fn touch_progress(search_term: &str, touch: Touch) -> Response {
+    match touch {
+        Touch::Required => Response::TouchRequired {
             account: search_term.to_owned(),
-            code: format!("{:06}", code),
+            touch_required: true,
         },
-        Err(e) => Response::Error {
-            error: format!("{:?}", e),
+        Touch::Waiting(elapsed) => Response::TouchWaiting {
+            account: search_term.to_owned(),
+            elapsed_ms: elapsed.as_millis() as u64,
         },
     }
 }
 
-pub fn serve() -> Result<(), Error> {
-    read().map(|r| handle_request(&r)).and_then(|r| write(&r))
+// a guessed credential may not be the one the caller had in mind
+fn match_warnings(name: &str, search_term: &str, matching: Matching) -> Vec<String> {
+    match matching {
+        Matching::Fuzzy(options) if !oath::is_exact_match(name, search_term, options) => {
+            vec![String::from("matched by fuzzy search")]
+        }
+        _ => Vec::new(),
+    }
 }
 
-fn read() -> Result<Request, Error> {
-    read_input(&mut io::stdin()).and_then(|r| deserialize_request(&r))
+fn default_account() -> Option<String> {
+    env::var("YKTOTP_DEFAULT_ACCOUNT").ok()
 }
 
-fn write(response: &Response) -> Result<(), Error> {
-    serialize_response(response).and_then(|r| write_output(&mut io::stdout(), &r))
+// an empty default is no default, so the request still fails as bad request
+fn with_default_account<'a>(account: &'a str, default: Option<&'a str>) -> &'a str {
+    match default {
+        Some(default) if account.trim().is_empty() => default,
+        _ => account,
+    }
 }
 
-fn read_input(buffer: &mut impl Read) -> Result<Vec<u8>, Error> {
-    let mut raw_input_length: [u8; 4] = [0; 4];
-    buffer
-        .read_exact(&mut raw_input_length)
-        .map_err(|_| Error::Read)?;
-    let input_length =
-        usize::try_from(u32::from_ne_bytes(raw_input_length)).map_err(|_| Error::Read)?;
+#[derive(Debug, Clone, Default)]
+struct ListOptions {
+    query: Option<String>,
+    sort: bool,
+    oath_type: Option<oath::OathType>,
+}
 
-    let mut raw_input = vec![0; input_length];
-    buffer.read_exact(&mut raw_input).map_err(|_| Error::Read)?;
+#[derive(Debug, Clone, Copy, Default)]
+struct CodeOptions {
+    timestamp: u64,
+    next: bool,
+    include_timestamp: bool,
+    stream: bool,
+    step_offset: i64,
+    client_time: Option<u64>,
+}
 
-    Ok(raw_input)
+// surrounding whitespace is a typo, inner runs only matter for an exact name
+fn normalize_search_term(search_term: &str, matching: Matching) -> String {
+    match matching {
+        Matching::Exact => search_term.trim().to_owned(),
+        Matching::Fuzzy(_) => search_term
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" "),
+    }
 }
 
-fn write_output(buffer: &mut impl Write, raw_output: &[u8]) -> Result<(), Error> {
-    buffer.write_all(raw_output).map_err(|_| Error::Write)
+#[derive(Debug, Clone, Copy)]
+enum Matching {
+    Exact,
+    Fuzzy(oath::MatchOptions),
 }
 
-fn deserialize_request(raw_input: &[u8]) -> Result<Request, Error> {
-    let input = std::str::from_utf8(raw_input).map_err(|_| Error::Read)?;
-    serde_json::from_str(input).map_err(|_| Error::Read)
+enum Touch {
+    Required,
+    Waiting(Duration),
 }
 
-fn serialize_response(response: &Response) -> Result<Vec<u8>, Error> {
-    let serialized = serde_json::to_string(response).map_err(|_| Error::Write)?;
-    let raw_output = serialized.as_bytes();
+enum Progress<T> {
+    Touch,
+    Done(Result<T, Error>),
+}
 
-    let output_length = u32::try_from(raw_output.len()).map_err(|_| Error::Write)?;
-    let raw_output_length = u32::to_ne_bytes(output_length);
+// the calculation blocks until the key is touched, so it runs in a thread we can stop waiting for
+fn calculate_with_touch<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    search_term: &str,
+    matching: Matching,
+    options: CodeOptions,
+    timeouts: Timeouts,
+    cache: DeviceCache,
+    on_touch: impl FnMut(Touch),
+) -> Result<(String, oath::Code, Option<oath::Code>), Error> {
+    let CodeOptions {
+        timestamp,
+        next,
+        stream,
+        step_offset,
+        ..
+    } = options;
+    let search_term = search_term.to_owned();
+    let cached = cache.credentials();
+    let listed = cached.is_none();
+    let work = move |touch: &dyn Fn()| {
+        let yubikey = connect()?;
+        let creds = credentials_of(&yubikey, cached, timestamp)?;
+        let calculate = |time| match matching {
+            Matching::Exact => {
+                oath::calculate_exact(&yubikey, &creds, &search_term, time, step_offset, touch)
+            }
+            Matching::Fuzzy(options) => oath::calculate_fuzzy(
+                &yubikey,
+                &creds,
+                &search_term,
+                options,
+                time,
+                step_offset,
+                touch,
+            ),
+        };
+        // the next window starts one period of the same credential later
+        let codes = calculate(timestamp)
+            .and_then(|(name, code)| match (next, code.period) {
+                (true, Some(period)) => {
+                    let next = timestamp
+                        .checked_add(period)
+                        .ok_or(oath::Error::TimeOutOfRange)?;
+                    let (_, next_code) = calculate(next)?;
+                    Ok((name, code, Some(next_code)))
+                }
+                _ => Ok((name, code, None)),
+            })
+            .map_err(Error::Oath)?;
+        Ok((creds, codes))
+    };
+    with_touch(work, stream, timeouts, on_touch)
+        .inspect(|(creds, _)| {
+            if listed {
+                cache.store_credentials(creds)
+            }
+        })
+        .map(|(_, codes)| codes)
+}
+
+// the work is told how to signal that it waits for a touch, which starts the touch timeout
+fn with_touch<T: Send + 'static>(
+    work: impl FnOnce(&dyn Fn()) -> Result<T, Error> + Send + 'static,
+    stream: bool,
+    timeouts: Timeouts,
+    mut on_touch: impl FnMut(Touch),
+) -> Result<T, Error> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let touch_sender = sender.clone();
+        let touch = move || {
+            let _ = touch_sender.send(Progress::Touch);
+        };
+        let _ = sender.send(Progress::Done(work(&touch)));
+    });
 
-    Ok([&raw_output_length, raw_output].concat())
+    let mut touched_at: Option<Instant> = None;
+    loop {
+        let timeout = match touched_at {
+            // a streaming request is woken up by the heartbeat to report its progress
+            Some(at) if stream => timeouts
+                .touch
+                .saturating_sub(at.elapsed())
+                .min(timeouts.heartbeat),
+            Some(at) => timeouts.touch.saturating_sub(at.elapsed()),
+            None => timeouts.request,
+        };
+        match receiver.recv_timeout(timeout) {
+            Ok(Progress::Touch) => {
+                on_touch(Touch::Required);
+                touched_at = Some(Instant::now());
+            }
+            Ok(Progress::Done(result)) => return result,
+            Err(mpsc::RecvTimeoutError::Timeout) => match touched_at {
+                Some(at) if at.elapsed() < timeouts.touch => on_touch(Touch::Waiting(at.elapsed())),
+                Some(_) => return Err(Error::Oath(oath::Error::TouchTimeout)),
+                None => return Err(Error::Timeout),
+            },
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                return Err(Error::Oath(oath::Error::Yubikey))
+            }
+        }
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use test_case::test_case;
+// the codes are answered in the order of the accounts, an error in place of a failing one;
+// the credentials are listed once for all of them
+fn read_many_otps<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    accounts: &[String],
+    timestamp: u64,
+    timeout: Duration,
+    cache: DeviceCache,
+) -> Response {
+    let accounts = accounts.to_vec();
+    let cached = cache.credentials();
+    let listed = cached.is_none();
+    let codes = with_timeout(timeout, move || {
+        let yubikey = connect()?;
+        let creds = credentials_of(&yubikey, cached, timestamp)?;
+        let codes = accounts
+            .iter()
+            .map(|account| read_listed_otp(&yubikey, &creds, account, timestamp))
+            .collect();
+        Ok((creds, codes))
+    });
 
-    use super::*;
+    match codes {
+        Ok((creds, codes)) => {
+            if listed {
+                cache.store_credentials(&creds);
+            }
+            Response::CodeMany { codes }
+        }
+        Err(e) => error_response(&e),
+    }
+}
 
-    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\"}", Request::Code { account: String::from("rust-lang.org")}; "works with proper json")]
-    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\",\"extra\":\"extra_field\"}", Request::Code { account: String::from("rust-lang.org")}; "ignores additional fields")]
-    #[test_case(b"{\"type\":\"AccountList\"}", Request::AccountList; "works with account list request")]
-    fn deserialize_request_succeeds(bytes: &[u8], request: Request) {
-        let deserialized = deserialize_request(bytes).unwrap();
-        assert_eq!(
-            request, deserialized,
-            "asserting equality of deserialized and expected request"
-        )
+// a credential requiring a touch is not calculated, like in `CodeAll`, as waiting for it
+// would hold up the accounts that follow
+fn read_listed_otp(
+    yubikey: &impl yubikey::SmartCard,
+    creds: &[oath::Credential],
+    account: &str,
+    timestamp: u64,
+) -> Response {
+    let options = oath::MatchOptions::default();
+    let search_term = normalize_search_term(account, Matching::Fuzzy(options));
+    if search_term.is_empty() {
+        return error_response(&Error::EmptySearchTerm);
+    }
+    let cred = match oath::find_listed(creds, &search_term, options) {
+        Ok(cred) => cred,
+        Err(oath::Error::TooManyMatchingCredentials(candidates)) => {
+            return Response::Ambiguous {
+                account: search_term,
+                candidates,
+            }
+        }
+        Err(e) => return error_response(&Error::Oath(e)),
+    };
+    if cred.touch {
+        return Response::TouchRequired {
+            account: search_term,
+            touch_required: true,
+        };
     }
 
-    #[test_case(b"{\"account\":\"rust-lang.org}"; "fails on illegal syntax")]
-    #[test_case(b"{\"account\":22}"; "fails on integer type")]
-    #[test_case(b"{\"no_account\":22}"; "fails on wrong key")]
-    #[test_case(b"{}"; "fails on empty json")]
-    #[test_case(b""; "fails on empty string")]
-    #[test_case(b"{\"account\":\"rust-lang.org\"}231412"; "fails on trailing chars")]
-    #[test_case(b"2134{\"account\":\"rust-lang.org\"}"; "fails on leading chars")]
-    fn deserialize_request_fails_on_illegal_json(bytes: &[u8]) {
-        assert!(
-            matches!(deserialize_request(bytes), Err(Error::Read)),
-            "asserting request deserialization results in error"
-        )
+    match oath::calculate(yubikey, &cred.id, timestamp) {
+        Ok(code) => Response::Code {
+            account: search_term,
+            code: format_code(&code),
+            valid_for: code.period.map(|period| valid_for(timestamp, period)),
+            next_code: None,
+            next_valid_for: None,
+            timestamp: None,
+            clock_skew: None,
+            warnings: Vec::new(),
+        },
+        Err(e) => error_response(&Error::Oath(e)),
     }
+}
 
-    #[test_case(& Response::Code{account: String::from("rust-lang.org"), code: String::from("123456")}, b"\x2B\x00\x00\x00{\"account\":\"rust-lang.org\",\"code\":\"123456\"}"; "succeeds for response with code")]
-    #[test_case(& Response::AccountList{accounts: vec ! [String::from("rust-lang.org"), String::from("zombo.com")]}, b"\x2A\x00\x00\x00{\"accounts\":[\"rust-lang.org\",\"zombo.com\"]}"; "succeeds for response with account list")]
-    #[test_case(& Response::Error{error: String::from("some error")}, b"\x16\x00\x00\x00{\"error\":\"some error\"}"; "succeeds for response with error")]
-    fn serialize_response_succeeds(response: &Response, bytes: &[u8]) {
-        let serialized = serialize_response(response).unwrap();
-        assert_eq!(
-            bytes, serialized,
-            "assert serialized response equals expected bytes"
-        )
+fn require_touch<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    timestamp: u64,
+    timeouts: Timeouts,
+) -> Response {
+    let work = move |touch: &dyn Fn()| {
+        let yubikey = connect()?;
+        oath::require_touch(&yubikey, timestamp, touch).map_err(Error::Oath)
+    };
+
+    match with_touch(work, false, timeouts, |_| {}) {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
     }
+}
 
-    #[test_case(
-    b"\x1B\x00\x00\x00{\"account\":\"rust-lang.org\"}",
-    b"{\"account\":\"rust-lang.org\"}";
-    "succeeds for correct input length"
-    )]
-    #[test_case(
-    b"\x1B\x00\x00\x00{\"account\":\"rust-lang.org\"}herearesomemorebytes",
-    b"{\"account\":\"rust-lang.org\"}";
-    "succeeds for additional bytes after input"
-    )]
-    fn read_input_succeeds(input_bytes: &[u8], output_bytes: &[u8]) {
-        let buffer = input_bytes.to_vec();
-        let read_bytes = read_input(&mut buffer.as_slice()).unwrap();
-        assert_eq!(
-            output_bytes, read_bytes,
-            "assert read bytes equal expected bytes"
-        )
+fn read_all_otps<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    timestamp: u64,
+) -> Response {
+    let codes = connect().and_then(|y| oath::calculate_all(&y, timestamp).map_err(Error::Oath));
+
+    match codes {
+        Ok(codes) => Response::CodeAll {
+            codes: account_codes(codes, timestamp),
+        },
+        Err(e) => error_response(&e),
     }
+}
 
-    #[test_case(b"\x1B\x00\x00\x00{\"account\":\"rust.org\"}"; "fails for too short input")]
-    fn read_input_fails(input_bytes: &[u8]) {
-        let buffer = input_bytes.to_vec();
-        assert!(
-            matches!(read_input(&mut buffer.as_slice()), Err(Error::Read)),
-            "assert reading input fails"
+// the issuer is compared like a search term, so it ignores case and surrounding whitespace
+fn read_issuer_otps<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    issuer: &str,
+    timestamp: u64,
+) -> Response {
+    let issuer = issuer.trim().to_lowercase();
+    if issuer.is_empty() {
+        return error_response(&Error::EmptySearchTerm);
+    }
+    let codes = connect().and_then(|y| oath::calculate_all(&y, timestamp).map_err(Error::Oath));
+
+    match codes {
+        Ok(codes) => Response::CodeAll {
+            codes: account_codes(
+                codes
+                    .into_iter()
+                    // only TOTP names carry a period prefix
+                    .filter(|entry| {
+                        oath::split_name(&entry.name, entry.oath_type)
+                            .0
+                            .is_some_and(|i| i.to_lowercase() == issuer)
+                    })
+                    .collect(),
+                timestamp,
+            ),
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn account_codes(codes: Vec<oath::CredentialCode>, timestamp: u64) -> Vec<AccountCode> {
+    codes
+        .into_iter()
+        .map(|entry| AccountCode {
+            account: entry.name,
+            code: entry.code.as_ref().map(format_code),
+            valid_for: entry
+                .code
+                .and_then(|code| code.period)
+                .map(|period| valid_for(timestamp, period)),
+            touch_required: entry.touch,
+        })
+        .collect()
+}
+
+fn resolve<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    search_term: &str,
+    cache: DeviceCache,
+) -> Response {
+    // the term is read like the one of a `Code` request, so both pick the same credential
+    let options = oath::MatchOptions::default();
+    let search_term = &normalize_search_term(search_term, Matching::Fuzzy(options));
+    if search_term.is_empty() {
+        return error_response(&Error::EmptySearchTerm);
+    }
+    let listing = match cache.listing() {
+        Some(listing) => Ok(listing),
+        None => connect()
+            .and_then(|y| oath::list_all_credentials(&y).map_err(Error::Oath))
+            .inspect(|listing| cache.store_listing(listing)),
+    };
+    let name = listing.and_then(|listing| {
+        oath::resolve_fuzzy(&listing.names(), search_term, options).map_err(Error::Oath)
+    });
+
+    match name {
+        Ok(name) => Response::Resolved { account: name },
+        Err(Error::Oath(oath::Error::TooManyMatchingCredentials(candidates))) => {
+            Response::Ambiguous {
+                account: search_term.to_owned(),
+                candidates,
+            }
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+fn verify<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error> + Send + 'static,
+    search_term: &str,
+    code: &str,
+    timestamp: u64,
+    window: u32,
+    timeouts: Timeouts,
+    notify: &mut dyn FnMut(&Response),
+) -> Response {
+    if i64::from(window) > MAX_STEP_OFFSET {
+        return error_response(&Error::WindowOutOfRange);
+    }
+    let term = search_term.to_owned();
+    let work = move |touch: &dyn Fn()| {
+        let yubikey = connect()?;
+        oath::calculate_window(
+            &yubikey,
+            &term,
+            oath::MatchOptions::default(),
+            timestamp,
+            window,
+            touch,
+        )
+        .map_err(Error::Oath)
+    };
+    let codes = with_touch(work, false, timeouts, |touch| {
+        notify(&touch_progress(search_term, touch))
+    });
+    // every step of the window is compared, so the time taken does not tell which one matched
+    let valid = codes.map(|(_, codes)| {
+        codes.iter().fold(false, |valid, expected| {
+            valid | constant_time_eq(format_code(expected).as_bytes(), code.as_bytes())
+        })
+    });
+
+    match valid {
+        Ok(valid) => Response::Verify {
+            account: search_term.to_owned(),
+            valid,
+        },
+        Err(Error::Oath(oath::Error::TooManyMatchingCredentials(candidates))) => {
+            Response::Ambiguous {
+                account: search_term.to_owned(),
+                candidates,
+            }
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+// the length of a code is no secret, only where its digits differ is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn code_from_secret(
+    secret_base32: &str,
+    algorithm: oath::Algorithm,
+    digits: u8,
+    period: u64,
+    timestamp: u64,
+) -> Response {
+    if period == 0 {
+        return error_response(&Error::InvalidCodeParameters);
+    }
+    let secret = match decode_secret(secret_base32, digits) {
+        Ok(secret) => secret,
+        Err(e) => return error_response(&e),
+    };
+
+    let code = oath::calculate_totp(&secret, algorithm, digits, period, timestamp);
+    Response::CodeFromSecret {
+        code: format_code(&code),
+        valid_for: Some(valid_for(timestamp, period)),
+    }
+}
+
+fn hotp_at(secret_base32: &str, algorithm: oath::Algorithm, digits: u8, counter: u64) -> Response {
+    let secret = match decode_secret(secret_base32, digits) {
+        Ok(secret) => secret,
+        Err(e) => return error_response(&e),
+    };
+
+    let code = oath::calculate_hotp_at(&secret, algorithm, digits, counter);
+    Response::CodeFromSecret {
+        code: format_code(&code),
+        valid_for: None,
+    }
+}
+
+// the digits the YubiKey supports, so the code is the one it would calculate
+fn decode_secret(secret_base32: &str, digits: u8) -> Result<Vec<u8>, Error> {
+    if !(6..=8).contains(&digits) {
+        return Err(Error::InvalidCodeParameters);
+    }
+    base32::decode(secret_base32).ok_or(Error::Uri(otpauth::Error::InvalidSecret))
+}
+
+fn read_hotp<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    search_term: &str,
+) -> Response {
+    let code = connect().and_then(|y| oath::calculate_hotp(&y, search_term).map_err(Error::Oath));
+
+    match code {
+        Ok(code) => Response::Code {
+            account: search_term.to_owned(),
+            code: format_code(&code),
+            valid_for: None,
+            next_code: None,
+            next_valid_for: None,
+            timestamp: None,
+            clock_skew: None,
+            warnings: Vec::new(),
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn add_credential<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    definition: &oath::CredentialDefinition,
+) -> Response {
+    let result = connect().and_then(|y| oath::add_credential(&y, definition).map_err(Error::Oath));
+
+    match result {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn import<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    uris: &[String],
+) -> Response {
+    let yubikey = match connect() {
+        Ok(yubikey) => yubikey,
+        Err(e) => return error_response(&e),
+    };
+
+    let results = uris
+        .iter()
+        .map(|uri| {
+            let result = otpauth::parse(uri)
+                .map_err(Error::Uri)
+                .and_then(|definition| {
+                    oath::add_credential(&yubikey, &definition).map_err(Error::Oath)
+                });
+            ImportItem {
+                uri: uri.clone(),
+                response: match result {
+                    Ok(()) => Response::Ok { ok: true },
+                    Err(e) => error_response(&e),
+                },
+            }
+        })
+        .collect();
+
+    Response::ImportResult { results }
+}
+
+fn import_migration<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    data: &str,
+) -> Response {
+    // a payload that cannot be read fails as a whole before the YubiKey is connected
+    let entries = match migration::parse(data) {
+        Ok(entries) => entries,
+        Err(e) => return error_response(&Error::Migration(e)),
+    };
+    let yubikey = match connect() {
+        Ok(yubikey) => yubikey,
+        Err(e) => return error_response(&e),
+    };
+
+    let results = entries
+        .into_iter()
+        .map(|entry| {
+            let result = entry
+                .definition
+                .map_err(Error::Migration)
+                .and_then(|definition| {
+                    oath::add_credential(&yubikey, &definition).map_err(Error::Oath)
+                });
+            MigrationItem {
+                account: entry.name,
+                response: match result {
+                    Ok(()) => Response::Ok { ok: true },
+                    Err(e) => error_response(&e),
+                },
+            }
+        })
+        .collect();
+
+    Response::MigrationResult { results }
+}
+
+fn delete_credential<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    name: &str,
+) -> Response {
+    let result = connect().and_then(|y| oath::delete_credential(&y, name).map_err(Error::Oath));
+
+    match result {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
+    }
+}
+
+// the account and policy are not looked at, as the version of the OATH application already
+// decides that the policy cannot be changed
+fn set_touch<Y: yubikey::SmartCard>(connect: impl FnOnce() -> Result<Y, Error>) -> Response {
+    let result =
+        connect().and_then(|y| oath::check_touch_update(y.select_response()).map_err(Error::Oath));
+
+    match result {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn credential_exists<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    name: &str,
+) -> Response {
+    let result = connect().and_then(|y| oath::credential_exists(&y, name).map_err(Error::Oath));
+
+    match result {
+        Ok(exists) => Response::Exists {
+            account: name.to_owned(),
+            exists,
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn rename_credential<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    from: &str,
+    to: &str,
+) -> Response {
+    let result = connect().and_then(|y| oath::rename_credential(&y, from, to).map_err(Error::Oath));
+
+    match result {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
+    }
+}
+
+// the salt of the key is part of the select response, which only a real YubiKey keeps
+fn set_password<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    new_password: Option<&str>,
+) -> Response {
+    let result = connect().and_then(|y| {
+        oath::set_password(&y, y.select_response(), new_password).map_err(Error::Oath)
+    });
+
+    match result {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn reset<Y: yubikey::SmartCard>(connect: impl FnOnce() -> Result<Y, Error>) -> Response {
+    let result = connect().and_then(|y| oath::reset(&y).map_err(Error::Oath));
+
+    match result {
+        Ok(()) => Response::Ok { ok: true },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn read_device_info<Y: yubikey::SmartCard>(connect: impl FnOnce() -> Result<Y, Error>) -> Response {
+    let info = connect().and_then(|y| y.device_info().map_err(Error::Yubikey));
+
+    match info {
+        Ok(info) => Response::DeviceInfo {
+            serial: info.serial,
+            version: info.version.to_string(),
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+// the version is read last, as it leaves the OTP application selected
+fn read_capacity<Y: yubikey::SmartCard>(connect: impl FnOnce() -> Result<Y, Error>) -> Response {
+    let capacity = connect().and_then(|y| {
+        let used = oath::list_credentials(&y).map_err(Error::Oath)?.len();
+        let max = y
+            .device_info()
+            .ok()
+            .and_then(|info| oath::max_credentials(&info.version));
+        Ok((used, max))
+    });
+
+    match capacity {
+        Ok((used, max)) => Response::Capacity { used, max },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn challenge_response<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    slot: u8,
+    challenge_hex: &str,
+) -> Response {
+    let slot = match yubikey::Slot::from_number(slot) {
+        Some(slot) => slot,
+        None => return error_response(&Error::InvalidSlot),
+    };
+    let challenge = match hex::decode(challenge_hex) {
+        Some(challenge) if challenge.len() <= yubikey::MAX_CHALLENGE_LENGTH => challenge,
+        _ => return error_response(&Error::InvalidChallenge),
+    };
+    let response = connect().and_then(|y| {
+        y.challenge_response(slot, &challenge)
+            .map_err(Error::Yubikey)
+    });
+
+    match response {
+        Ok(response) => Response::Hmac {
+            response_hex: hex::encode(&response),
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn read_oath_info<Y: yubikey::SmartCard>(connect: impl FnOnce() -> Result<Y, Error>) -> Response {
+    match connect() {
+        Ok(yubikey) => oath_info(yubikey.select_response()),
+        Err(e) => error_response(&e),
+    }
+}
+
+fn oath_info(select_response: &[u8]) -> Response {
+    Response::OathInfo {
+        version: oath::applet_version(select_response).map(|version| version.to_string()),
+        oath_locked: oath::is_locked(select_response),
+    }
+}
+
+// only the round trip is timed, connecting to the reader takes longer and varies by platform
+fn measure_latency<Y: yubikey::SmartCard>(
+    connect: impl FnOnce() -> Result<Y, Error>,
+    aid: &[u8],
+    now: impl Fn() -> Instant,
+) -> Response {
+    let elapsed = connect().and_then(|y| {
+        let start = now();
+        yubikey::select_applet(&y, aid).map_err(Error::Yubikey)?;
+        Ok(now().duration_since(start))
+    });
+
+    match elapsed {
+        Ok(elapsed) => Response::Latency {
+            millis: elapsed.as_millis() as u64,
+        },
+        Err(e) => error_response(&e),
+    }
+}
+
+fn read_device_list() -> Response {
+    match yubikey::list_devices() {
+        Ok(devices) => Response::DeviceList {
+            devices: devices
+                .into_iter()
+                .map(|info| Device {
+                    serial: info.serial,
+                    version: info.version.to_string(),
+                })
+                .collect(),
+        },
+        Err(e) => error_response(&Error::Yubikey(e)),
+    }
+}
+
+fn error_response(error: &Error) -> Response {
+    let (kind, message) = describe_error(error);
+    let message = match error {
+        Error::InvalidUtf8(offset) => format!("{} at byte {}", message, offset),
+        Error::BadJson(detail) => format!("{}: {}", message, detail),
+        Error::UnknownRequestType(request_type) => format!(
+            "{} {}, supported are {}",
+            message,
+            request_type,
+            REQUEST_TYPES.join(", ")
+        ),
+        _ => message.to_owned(),
+    };
+    let message = sanitize_message(&message);
+    log::log(format_args!(
+        "failed with {}: {}",
+        kind,
+        log_message(error, &message)
+    ));
+    Response::Error {
+        kind: kind.to_owned(),
+        message,
+    }
+}
+
+fn sanitize_message(message: &str) -> String {
+    let printable: Vec<char> = message.chars().filter(|c| !c.is_control()).collect();
+    if printable.len() <= MAX_ERROR_MESSAGE_LENGTH {
+        return printable.into_iter().collect();
+    }
+    printable[..MAX_ERROR_MESSAGE_LENGTH - 1]
+        .iter()
+        .chain(['…'].iter())
+        .collect()
+}
+
+// the PC/SC status only helps diagnosing the device, so it is logged but not answered; the serde
+// detail may quote a value of the message, e.g. a password, so it is answered but not logged
+fn log_message(error: &Error, message: &str) -> String {
+    match error {
+        Error::BadJson(_) => describe_error(error).1.to_owned(),
+        Error::Yubikey(e) => match e.status() {
+            Some(status) => format!(
+                "{} (PC/SC status {:?}, 0x{:08x})",
+                message, status, status as u32
+            ),
+            None => message.to_owned(),
+        },
+        _ => message.to_owned(),
+    }
+}
+
+fn describe_error(error: &Error) -> (&'static str, &'static str) {
+    match error {
+        Error::Read => ("bad_request", "Could not read request"),
+        Error::BadJson(_) => ("bad_request", "Could not read request"),
+        Error::UnknownRequestType(_) => ("bad_request", "Unknown request type"),
+        Error::InvalidUtf8(_) => ("invalid_utf8", "Request is not valid UTF-8"),
+        Error::Write => ("write_failure", "Could not write response"),
+        Error::ResponseTooLarge => (
+            "response_too_large",
+            "Response exceeds the maximum message size",
+        ),
+        Error::Unconfirmed => ("confirmation_required", "Request has to be confirmed"),
+        Error::EmptySearchTerm => ("bad_request", "Account must not be empty"),
+        Error::StepOffsetOutOfRange => ("bad_request", "Step offset is out of range"),
+        Error::WindowOutOfRange => ("bad_request", "Window is out of range"),
+        Error::InvalidSlot => ("bad_request", "Slot has to be 1 or 2"),
+        Error::InvalidChallenge => (
+            "bad_request",
+            "Challenge has to be hex encoded and at most 64 bytes long",
+        ),
+        Error::InvalidCodeParameters => (
+            "bad_request",
+            "Digits have to be between 6 and 8 and the period above zero",
+        ),
+        Error::Timeout => ("timeout", "YubiKey did not respond in time"),
+        Error::Uri(e) => match e {
+            otpauth::Error::Malformed => ("invalid_uri", "Not a valid otpauth URI"),
+            otpauth::Error::MissingSecret => ("missing_secret", "URI contains no secret"),
+            otpauth::Error::InvalidSecret => ("invalid_secret", "Secret is not valid base32"),
+        },
+        Error::Migration(e) => match e {
+            migration::Error::Malformed => ("invalid_migration", "Not a valid migration payload"),
+            migration::Error::UnsupportedAlgorithm => {
+                ("unsupported", "Algorithm is not supported by the YubiKey")
+            }
+        },
+        Error::Yubikey(e) => match e {
+            yubikey::Error::NoDevice => ("no_device", "No YubiKey found"),
+            yubikey::Error::MoreThanOneYubikey => {
+                ("multiple_devices", "More than one YubiKey found")
+            }
+            yubikey::Error::Connection(_) => ("device_error", "Could not connect to YubiKey"),
+            yubikey::Error::Busy => ("device_busy", "YubiKey is in use by another application"),
+            yubikey::Error::Transmission(_) => {
+                ("device_error", "Could not communicate with YubiKey")
+            }
+            yubikey::Error::Removed => ("device_removed", "YubiKey was removed"),
+            yubikey::Error::SlotNotConfigured => (
+                "slot_not_configured",
+                "Slot is not configured for challenge-response",
+            ),
+            yubikey::Error::NoDeviceForTransport => (
+                "no_device_for_transport",
+                "No YubiKey found over the requested transport",
+            ),
+            yubikey::Error::NoMatchingSerial => (
+                "no_matching_device",
+                "No YubiKey with the requested serial found",
+            ),
+            yubikey::Error::AppletNotFound => (
+                "applet_not_found",
+                "OATH application not found on the YubiKey",
+            ),
+            // the request is checked before, so this is only a safeguard
+            yubikey::Error::ChallengeTooLong => {
+                ("bad_request", "Challenge is longer than 64 bytes")
+            }
+        },
+        Error::Oath(e) => match e {
+            oath::Error::Yubikey => ("oath_failure", "OATH application failed"),
+            oath::Error::NoMatchingCredential => ("no_match", "No matching credential found"),
+            oath::Error::CredentialExists => (
+                "already_exists",
+                "A credential with this name already exists",
+            ),
+            oath::Error::TooManyMatchingCredentials(_) => {
+                ("ambiguous", "More than one matching credential found")
+            }
+            // distinct from a wrong password, so the caller knows to ask for one
+            oath::Error::AuthRequired => (
+                "locked",
+                "OATH application is locked, a password is required",
+            ),
+            oath::Error::InvalidSecret => ("invalid_secret", "Secret is not valid base32"),
+            oath::Error::Rejected => ("device_rejected", "YubiKey rejected the command"),
+            oath::Error::WrongPassword => ("wrong_password", "Wrong password"),
+            oath::Error::TouchTimeout => ("touch_timeout", "YubiKey was not touched in time"),
+            oath::Error::NoTouchCredential => {
+                ("unsupported", "No credential requiring a touch found")
+            }
+            oath::Error::TouchPolicyFixed => (
+                "unsupported",
+                "YubiKey cannot change the touch policy of a credential",
+            ),
+            oath::Error::TimeOutOfRange => ("bad_request", "Timestamp is out of range"),
+            oath::Error::NameTooLong => ("bad_request", "Name is longer than 64 bytes"),
+            oath::Error::InvalidDigits => ("bad_request", "Digits have to be between 6 and 8"),
+        },
+    }
+}
+
+// the device only truncates the HMAC, reducing it to the digits of the credential is up to us
+fn format_code(code: &oath::Code) -> String {
+    if code.steam {
+        return format_steam_code(code.value);
+    }
+
+    // the value is already reduced to the digits of the credential
+    format!("{:0width$}", code.value, width = usize::from(code.digits))
+}
+
+fn format_steam_code(value: u32) -> String {
+    let base = STEAM_ALPHABET.len() as u32;
+    (0..STEAM_DIGITS as u32)
+        .map(|position| STEAM_ALPHABET[(value / base.pow(position) % base) as usize] as char)
+        .collect()
+}
+
+fn valid_for(timestamp: u64, period: u64) -> u64 {
+    period - (timestamp % period)
+}
+
+pub fn serve_loop() -> Result<(), Error> {
+    match idle_timeout() {
+        Some(idle) => serve(IdleReader::new(io::stdin(), idle), io::stdout()),
+        None => serve(io::stdin(), io::stdout()),
+    }
+}
+
+// without the variable the session only ends when the browser closes stdin
+fn idle_timeout() -> Option<Duration> {
+    env::var("YKTOTP_IDLE_TIMEOUT")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .map(Duration::from_secs)
+}
+
+// reading blocks, so it is moved to a thread whose data can be waited for with a timeout;
+// once nothing arrived in time, the input ends as if it was closed
+struct IdleReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    idle: Duration,
+    pending: io::Cursor<Vec<u8>>,
+}
+
+impl IdleReader {
+    fn new(mut input: impl Read + Send + 'static, idle: Duration) -> IdleReader {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 8192];
+            loop {
+                let chunk = input.read(&mut buffer).map(|n| buffer[..n].to_vec());
+                let last = !matches!(&chunk, Ok(data) if !data.is_empty());
+                if sender.send(chunk).is_err() || last {
+                    return;
+                }
+            }
+        });
+        IdleReader {
+            receiver,
+            idle,
+            pending: io::Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl Read for IdleReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.position() as usize == self.pending.get_ref().len() {
+            match self.receiver.recv_timeout(self.idle) {
+                Ok(chunk) => self.pending = io::Cursor::new(chunk?),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    log::log(format_args!("session idle for {:?}", self.idle));
+                    return Ok(0);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        self.pending.read(buf)
+    }
+}
+
+// allows embedding the host with streams other than stdio, e.g. a socket
+pub fn serve(input: impl Read, output: impl Write) -> Result<(), Error> {
+    serve_framed(input, output, Framing::LengthPrefix(byte_order()))
+}
+
+pub fn serve_jsonl(input: impl Read, output: impl Write) -> Result<(), Error> {
+    serve_framed(input, output, Framing::JsonLines)
+}
+
+fn serve_framed(input: impl Read, mut output: impl Write, framing: Framing) -> Result<(), Error> {
+    let mut input = BufReader::new(input);
+    let cache = ListingCache::new(timeout_from_env(
+        "YKTOTP_LIST_CACHE_TTL",
+        DEFAULT_LISTING_TTL,
+    ));
+    let result = run_loop(&mut input, &mut output, framing, |message, notify| {
+        handle_request_cached(message, notify, &cache)
+    });
+    if let Err(e) = &result {
+        log::log(format_args!("session ended with {}", describe_error(e).0));
+    }
+    result
+}
+
+// answers a single request without framing, every response as JSON on its own line
+pub fn serve_once(message: Result<Message, Error>, output: &mut impl Write) -> Result<(), Error> {
+    let message = match message {
+        Ok(message) => message,
+        Err(e) => return write_line(output, &error_response(&e)),
+    };
+    let mut notified = Ok(());
+    let reply = answer(&handle_request, &message, &mut |r| {
+        if notified.is_ok() {
+            notified = write_line(output, r);
+        }
+    });
+    notified?;
+    write_line(output, &reply)
+}
+
+fn run_loop(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    framing: Framing,
+    handler: impl Fn(&Message, &mut dyn FnMut(&Response)) -> Response,
+) -> Result<(), Error> {
+    loop {
+        // the browser ends the session by closing stdin
+        let raw_input = match framing.read(input)? {
+            Some(raw_input) => raw_input,
+            None => return Ok(()),
+        };
+        // a well-framed message that is no valid request only fails itself
+        let input = deserialize_request(&raw_input);
+        log::log_framing(format_args!(
+            "message {}",
+            match &input {
+                Ok(_) => "deserialized",
+                Err(rejected) => describe_error(&rejected.error).0,
+            }
+        ));
+        let input = match input {
+            Ok(input) => input,
+            Err(rejected) => {
+                framing.write(output, &rejected_reply(rejected))?;
+                continue;
+            }
+        };
+
+        let mut notified = Ok(());
+        let mut notify = |r: &Reply| {
+            if notified.is_ok() {
+                notified = framing.write(output, r);
+            }
+        };
+        match input {
+            Input::Single(message) => {
+                let reply = answer(&handler, &message, &mut notify);
+                notified?;
+                framing.write(output, &reply)?;
+                if message.request == Request::Close {
+                    return Ok(());
+                }
+            }
+            // every message of a batch is answered in its slot, failed or not
+            Input::Batch(messages) => {
+                let replies = messages
+                    .iter()
+                    .map(|message| match message {
+                        Ok(message) => answer(&handler, message, &mut notify),
+                        Err(rejected) => Reply {
+                            id: rejected.id.clone(),
+                            response: error_response(&rejected.error),
+                        },
+                    })
+                    .collect();
+                notified?;
+                framing.write(output, &Response::Batch(replies))?;
+                // the rest of a batch is still answered, the session ends after it
+                let close = messages
+                    .iter()
+                    .any(|message| matches!(message, Ok(m) if m.request == Request::Close));
+                if close {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn rejected_reply(rejected: Rejected) -> Reply {
+    Reply {
+        id: rejected.id,
+        response: error_response(&rejected.error),
+    }
+}
+
+// the id of a message is copied verbatim into every response to it
+fn answer(
+    handler: &impl Fn(&Message, &mut dyn FnMut(&Response)) -> Response,
+    message: &Message,
+    notify: &mut dyn FnMut(&Reply),
+) -> Reply {
+    let reply = |response| Reply {
+        id: message.id.clone(),
+        response: if message.envelope {
+            envelop(response)
+        } else {
+            response
+        },
+    };
+    match message.serial {
+        Some(serial) => log::log(format_args!(
+            "{} request for YubiKey {}",
+            message.request.kind(),
+            serial
+        )),
+        None => log::log(format_args!("{} request", message.request.kind())),
+    }
+    let response = handler(message, &mut |r| notify(&reply(r.clone())));
+    reply(response)
+}
+
+fn envelop(response: Response) -> Response {
+    match response {
+        Response::Error { .. } => Response::EnvelopedError {
+            ok: false,
+            error: Box::new(response),
+        },
+        _ => Response::Enveloped {
+            ok: true,
+            data: Box::new(response),
+        },
+    }
+}
+
+// the browser drops a message beyond its limit along with the connection, so a response too
+// large to send is replaced by an error that fits
+fn write(
+    output: &mut impl Write,
+    response: &impl Answer,
+    byte_order: ByteOrder,
+) -> Result<(), Error> {
+    let message = match serialize_response(response, byte_order) {
+        Err(Error::ResponseTooLarge) => serialize_response(&response.too_large(), byte_order),
+        message => message,
+    }?;
+    write_output(output, &message)
+}
+
+fn write_line(output: &mut impl Write, response: &impl Answer) -> Result<(), Error> {
+    let mut line = match serialize_line(response) {
+        Err(Error::ResponseTooLarge) => serialize_line(&response.too_large()),
+        line => line,
+    }?;
+    line.push(b'\n');
+    write_output(output, &line)
+}
+
+/// What is written to the client, along with the error replacing it if it is too large to send.
+trait Answer: Serialize {
+    fn too_large(&self) -> Reply;
+}
+
+// the error keeps the id and envelope, so the client still knows which request failed
+impl Answer for Reply {
+    fn too_large(&self) -> Reply {
+        let error = error_response(&Error::ResponseTooLarge);
+        Reply {
+            id: self.id.clone(),
+            response: match self.response {
+                Response::Enveloped { .. } | Response::EnvelopedError { .. } => envelop(error),
+                _ => error,
+            },
+        }
+    }
+}
+
+// a bare response has no id to keep, e.g. a batch answering several requests
+impl Answer for Response {
+    fn too_large(&self) -> Reply {
+        Reply {
+            id: None,
+            response: error_response(&Error::ResponseTooLarge),
+        }
+    }
+}
+
+fn serialize_line(response: &impl Serialize) -> Result<Vec<u8>, Error> {
+    let line = serde_json::to_vec(response).map_err(|_| Error::Write)?;
+    if line.len() > MAX_MESSAGE_SIZE {
+        return Err(Error::ResponseTooLarge);
+    }
+    Ok(line)
+}
+
+fn read_input(buffer: &mut impl Read, byte_order: ByteOrder) -> Result<Option<Vec<u8>>, Error> {
+    let mut raw_input_length: [u8; 4] = [0; 4];
+    let mut read_length = 0;
+    while read_length < raw_input_length.len() {
+        match buffer.read(&mut raw_input_length[read_length..]) {
+            Ok(0) if read_length == 0 => return Ok(None),
+            Ok(0) => return Err(Error::Read),
+            Ok(n) => read_length += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return Err(Error::Read),
+        }
+    }
+    let input_length =
+        usize::try_from(byte_order.decode(raw_input_length)).map_err(|_| Error::Read)?;
+    if input_length > MAX_MESSAGE_SIZE {
+        log::log_framing(format_args!(
+            "length prefix {} exceeds the maximum message size",
+            input_length
+        ));
+        return Err(Error::Read);
+    }
+
+    let (raw_input, frame) = read_body(buffer, input_length)?;
+    log::log_framing(format_args!("{}", frame));
+    if frame.received < frame.length {
+        return Err(Error::Read);
+    }
+
+    Ok(Some(raw_input))
+}
+
+// how much of a message arrived, without its content, which may carry secrets
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    length: usize,
+    received: usize,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "length prefix {}, {} bytes of the message received",
+            self.length, self.received
+        )
+    }
+}
+
+// a body cut short is read up to its end, so the missing bytes can be told
+fn read_body(buffer: &mut impl Read, length: usize) -> Result<(Vec<u8>, Frame), Error> {
+    let mut body = Vec::with_capacity(length);
+    buffer
+        .take(length as u64)
+        .read_to_end(&mut body)
+        .map_err(|_| Error::Read)?;
+    let frame = Frame {
+        length,
+        received: body.len(),
+    };
+    Ok((body, frame))
+}
+
+// blank lines are skipped, a line beyond the maximum message size ends the session like a
+// message with a length prefix beyond it
+fn read_json_line(buffer: &mut impl BufRead) -> Result<Option<Vec<u8>>, Error> {
+    loop {
+        let mut line = Vec::new();
+        let limit = MAX_MESSAGE_SIZE as u64 + 2;
+        match buffer.by_ref().take(limit).read_until(b'\n', &mut line) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(_) => return Err(Error::Read),
+        }
+        let content = line
+            .strip_suffix(b"\n")
+            .map(|l| l.strip_suffix(b"\r").unwrap_or(l))
+            .unwrap_or(&line);
+        if content.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::Read);
+        }
+        if !content.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Some(content.to_vec()));
+        }
+    }
+}
+
+// the length prefix and the payload go out in one write_all, which retries short and
+// interrupted writes until the whole message is written or the output fails
+fn write_output(buffer: &mut impl Write, raw_output: &[u8]) -> Result<(), Error> {
+    buffer.write_all(raw_output).map_err(|_| Error::Write)?;
+    buffer.flush().map_err(|_| Error::Write)
+}
+
+fn deserialize_request(raw_input: &[u8]) -> Result<Input, Rejected> {
+    let input = std::str::from_utf8(raw_input).map_err(|e| Error::InvalidUtf8(e.valid_up_to()))?;
+    match serde_json::from_str(input).map_err(bad_json)? {
+        serde_json::Value::Array(messages) => Ok(Input::Batch(
+            messages.into_iter().map(deserialize_identified).collect(),
+        )),
+        message => deserialize_identified(message).map(Input::Single),
+    }
+}
+
+// the id is taken before the rest of the message is read, which may fail
+fn deserialize_identified(message: serde_json::Value) -> Result<Message, Rejected> {
+    let id = message
+        .get("id")
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_owned);
+    deserialize_message(message).map_err(|error| Rejected { id, error })
+}
+
+pub fn deserialize_message(message: serde_json::Value) -> Result<Message, Error> {
+    match message.get("type").and_then(serde_json::Value::as_str) {
+        Some(request_type)
+            if !REQUEST_TYPES.contains(&request_type)
+                && !HIDDEN_REQUEST_TYPES.contains(&request_type) =>
+        {
+            Err(Error::UnknownRequestType(request_type.to_owned()))
+        }
+        _ => serde_json::from_value(message).map_err(bad_json),
+    }
+}
+
+// the serde message tells a missing field from a wrong type or trailing data
+pub fn bad_json(error: serde_json::Error) -> Error {
+    Error::BadJson(error.to_string())
+}
+
+fn serialize_response(response: &impl Serialize, byte_order: ByteOrder) -> Result<Vec<u8>, Error> {
+    let serialized = serde_json::to_string(response).map_err(|_| Error::Write)?;
+    let raw_output = serialized.as_bytes();
+    if raw_output.len() > MAX_MESSAGE_SIZE {
+        return Err(Error::ResponseTooLarge);
+    }
+
+    let output_length = u32::try_from(raw_output.len()).map_err(|_| Error::Write)?;
+    let raw_output_length = byte_order.encode(output_length);
+
+    let mut message = Vec::with_capacity(raw_output_length.len() + raw_output.len());
+    message.extend_from_slice(&raw_output_length);
+    message.extend_from_slice(raw_output);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::Cursor;
+    use test_case::test_case;
+
+    use crate::yubikey::mock::MockCard;
+
+    use super::*;
+
+    // the single TOTP credential Vault, whose code at 59 seconds is 287082
+    const VAULT_LIST: &[u8] = b"\x72\x06\x21Vault\x90\x00";
+    const VAULT_CODES: &[u8] = b"\x71\x05Vault\x76\x05\x06\x41\x39\x7e\xea\x90\x00";
+    const VAULT_TOUCH_CODES: &[u8] = b"\x71\x05Vault\x7c\x01\x06\x90\x00";
+    const VAULT_CODE: &[u8] = b"\x76\x05\x06\x41\x39\x7e\xea\x90\x00";
+
+    // lists Vault, then answers with the given responses
+    fn vault_card(responses: &[&[u8]]) -> MockCard {
+        MockCard::new(&[&[VAULT_LIST], responses].concat())
+    }
+
+    // the options of a plain code request at 59 seconds, each test sets only what it exercises
+    fn code_options() -> CodeOptions {
+        CodeOptions {
+            timestamp: 59,
+            ..CodeOptions::default()
+        }
+    }
+
+    fn single(input: Result<Input, Rejected>) -> Message {
+        match input {
+            Ok(Input::Single(message)) => message,
+            input => panic!("unexpected input {:?}", input),
+        }
+    }
+
+    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\"}", Request::Code { account: String::from("rust-lang.org"), timestamp: None, case_insensitive: true, next: false, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "works with proper json")]
+    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\",\"extra\":\"extra_field\"}", Request::Code { account: String::from("rust-lang.org"), timestamp: None, case_insensitive: true, next: false, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "ignores additional fields")]
+    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\",\"timestamp\":59}", Request::Code { account: String::from("rust-lang.org"), timestamp: Some(59), case_insensitive: true, next: false, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "works with timestamp")]
+    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\",\"case_insensitive\":false}", Request::Code { account: String::from("rust-lang.org"), timestamp: None, case_insensitive: false, next: false, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "works with case sensitive matching")]
+    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\",\"include_timestamp\":true}", Request::Code { account: String::from("rust-lang.org"), timestamp: None, case_insensitive: true, next: false, include_timestamp: true, stream: false, step_offset: 0, client_time: None }; "works with included timestamp")]
+    #[test_case(b"{\"type\":\"Code\"}", Request::Code { account: String::new(), timestamp: None, case_insensitive: true, next: false, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "works without account")]
+    #[test_case(b"{\"type\":\"CodeMany\",\"accounts\":[\"sso\",\"vpn\"]}", Request::CodeMany { accounts: vec![String::from("sso"), String::from("vpn")] }; "works with code many request")]
+    #[test_case(b"{\"type\":\"CodeByIssuer\",\"issuer\":\"AWS\"}", Request::CodeByIssuer { issuer: String::from("AWS") }; "works with code by issuer request")]
+    #[test_case(b"{\"type\":\"Verify\",\"account\":\"vault\",\"code\":\"287082\"}", Request::Verify { account: String::from("vault"), code: String::from("287082"), window: 1, timestamp: None }; "works with verify request")]
+    #[test_case(b"{\"type\":\"Code\",\"account\":\"rust-lang.org\",\"step_offset\":-1}", Request::Code { account: String::from("rust-lang.org"), timestamp: None, case_insensitive: true, next: false, include_timestamp: false, stream: false, step_offset: -1, client_time: None }; "works with step offset")]
+    #[test_case(b"{\"type\":\"AccountList\"}", Request::AccountList { query: None, sort: false, oath_type: None }; "works with account list request")]
+    #[test_case(b"{\"type\":\"AccountList\",\"query\":\"git\"}", Request::AccountList { query: Some(String::from("git")), sort: false, oath_type: None }; "works with account list query")]
+    #[test_case(b"{\"type\":\"AccountList\",\"sort\":true}", Request::AccountList { query: None, sort: true, oath_type: None }; "works with sorted account list")]
+    #[test_case(b"{\"type\":\"AccountList\",\"oath_type\":\"hotp\"}", Request::AccountList { query: None, sort: false, oath_type: Some(oath::OathType::Hotp) }; "works with account list of one type")]
+    #[test_case(b"{\"type\":\"CodeAll\"}", Request::CodeAll; "works with code all request")]
+    #[test_case(b"{\"type\":\"Resolve\",\"account\":\"git\"}", Request::Resolve { account: String::from("git") }; "works with resolve request")]
+    #[test_case(
+    b"{\"type\":\"AddCredential\",\"name\":\"rust-lang.org\",\"secret\":\"MZXW6YTBOI\",\"algorithm\":\"sha1\",\"digits\":6,\"oath_type\":\"totp\"}",
+    Request::AddCredential(oath::CredentialDefinition {
+        name: String::from("rust-lang.org"),
+        secret: String::from("MZXW6YTBOI"),
+        algorithm: oath::Algorithm::Sha1,
+        digits: 6,
+        oath_type: oath::OathType::Totp,
+        counter: 0,
+    });
+    "works with add credential request"
+    )]
+    #[test_case(b"{\"type\":\"CodeExact\",\"account\":\"rust-lang.org\"}", Request::CodeExact { account: String::from("rust-lang.org"), timestamp: None, next: false, include_timestamp: false, stream: false }; "works with exact code request")]
+    #[test_case(b"{\"type\":\"HotpCode\",\"account\":\"rust-lang.org\"}", Request::HotpCode { account: String::from("rust-lang.org")}; "works with hotp code request")]
+    #[test_case(b"{\"type\":\"DeleteCredential\",\"account\":\"rust-lang.org\"}", Request::DeleteCredential { account: String::from("rust-lang.org")}; "works with delete credential request")]
+    #[test_case(b"{\"type\":\"Exists\",\"account\":\"git\"}", Request::Exists { account: String::from("git") }; "works with exists request")]
+    #[test_case(b"{\"type\":\"SetPassword\",\"new_password\":\"secret\"}", Request::SetPassword { new_password: Some(String::from("secret")) }; "works with set password request")]
+    #[test_case(b"{\"type\":\"SetPassword\"}", Request::SetPassword { new_password: None }; "works with clear password request")]
+    #[test_case(b"{\"type\":\"RenameCredential\",\"from\":\"old\",\"to\":\"new\"}", Request::RenameCredential { from: String::from("old"), to: String::from("new") }; "works with rename credential request")]
+    #[test_case(b"{\"type\":\"ImportMigration\",\"data\":\"CgA=\"}", Request::ImportMigration { data: String::from("CgA=") }; "works with import migration request")]
+    #[test_case(b"{\"type\":\"Close\"}", Request::Close; "works with close request")]
+    #[test_case(b"{\"type\":\"CredentialInfo\",\"account\":\"github\"}", Request::CredentialInfo { account: String::from("github") }; "works with credential info request")]
+    #[test_case(b"{\"type\":\"SetTouch\",\"account\":\"github\",\"require_touch\":true}", Request::SetTouch { account: String::from("github"), require_touch: true }; "works with set touch request")]
+    #[test_case(b"{\"type\":\"Version\"}", Request::Version; "works with version request")]
+    #[test_case(b"{\"type\":\"Ping\"}", Request::Ping; "works with ping request")]
+    #[test_case(b"{\"type\":\"Reset\",\"confirm\":true}", Request::Reset { confirm: true }; "works with confirmed reset request")]
+    #[test_case(b"{\"type\":\"Reset\"}", Request::Reset { confirm: false }; "works with unconfirmed reset request")]
+    fn deserialize_request_succeeds(bytes: &[u8], request: Request) {
+        let deserialized = single(deserialize_request(bytes));
+        assert_eq!(
+            request, deserialized.request,
+            "asserting equality of deserialized and expected request"
+        )
+    }
+
+    #[test_case(b"{\"type\":\"AccountList\"}", None; "works without password")]
+    #[test_case(b"{\"type\":\"AccountList\",\"password\":\"secret\"}", Some(String::from("secret")); "works with password")]
+    fn deserialize_request_reads_password(bytes: &[u8], password: Option<String>) {
+        let deserialized = single(deserialize_request(bytes));
+        assert_eq!(
+            password, deserialized.password,
+            "asserting deserialized password equals expected password"
+        )
+    }
+
+    #[test_case(b"{\"type\":\"AccountList\"}", None; "works without id")]
+    #[test_case(b"{\"type\":\"AccountList\",\"id\":\"42\"}", Some(String::from("42")); "works with id")]
+    fn deserialize_request_reads_id(bytes: &[u8], id: Option<String>) {
+        let deserialized = single(deserialize_request(bytes));
+        assert_eq!(
+            id, deserialized.id,
+            "asserting equality of deserialized and expected id"
+        )
+    }
+
+    #[test_case(b"{\"type\":\"AccountList\"}", None; "works without serial")]
+    #[test_case(b"{\"type\":\"AccountList\",\"serial\":12345678}", Some(12345678); "works with serial")]
+    fn deserialize_request_reads_serial(bytes: &[u8], serial: Option<u32>) {
+        let deserialized = single(deserialize_request(bytes));
+        assert_eq!(
+            serial, deserialized.serial,
+            "asserting deserialized serial equals expected serial"
+        )
+    }
+
+    #[test_case(b"{\"type\":\"AccountList\"}", yubikey::Transport::Any; "works without transport")]
+    #[test_case(b"{\"type\":\"AccountList\",\"transport\":\"nfc\"}", yubikey::Transport::Nfc; "works with nfc transport")]
+    #[test_case(b"{\"type\":\"AccountList\",\"transport\":\"usb\"}", yubikey::Transport::Usb; "works with usb transport")]
+    fn deserialize_request_reads_transport(bytes: &[u8], transport: yubikey::Transport) {
+        let deserialized = single(deserialize_request(bytes));
+        assert_eq!(
+            transport, deserialized.transport,
+            "asserting deserialized transport equals expected transport"
+        )
+    }
+
+    #[test_case(b"{\"account\":\"rust-lang.org}"; "fails on illegal syntax")]
+    #[test_case(b"{\"account\":22}"; "fails on integer type")]
+    #[test_case(b"{\"no_account\":22}"; "fails on wrong key")]
+    #[test_case(b"{}"; "fails on empty json")]
+    #[test_case(b""; "fails on empty string")]
+    #[test_case(b"{\"account\":\"rust-lang.org\"}231412"; "fails on trailing chars")]
+    #[test_case(b"2134{\"account\":\"rust-lang.org\"}"; "fails on leading chars")]
+    fn deserialize_request_fails_on_illegal_json(bytes: &[u8]) {
+        assert!(
+            matches!(
+                deserialize_request(bytes),
+                Err(Rejected {
+                    error: Error::BadJson(_),
+                    ..
+                })
+            ),
+            "asserting request deserialization results in error"
+        )
+    }
+
+    #[test_case(& Response::Code{account: String::from("rust-lang.org"), code: String::from("123456"), valid_for: Some(30), next_code: None, next_valid_for: None, timestamp: None, clock_skew: None, warnings: Vec::new()}, b"\x3A\x00\x00\x00{\"account\":\"rust-lang.org\",\"code\":\"123456\",\"valid_for\":30}"; "succeeds for response with code")]
+    #[test_case(& Response::Code{account: String::from("rust-lang.org"), code: String::from("123456"), valid_for: Some(7), next_code: None, next_valid_for: None, timestamp: None, clock_skew: None, warnings: Vec::new()}, b"\x39\x00\x00\x00{\"account\":\"rust-lang.org\",\"code\":\"123456\",\"valid_for\":7}"; "succeeds for response with code emitted mid-window")]
+    #[test_case(& Response::Code{account: String::from("rust-lang.org"), code: String::from("123456"), valid_for: None, next_code: None, next_valid_for: None, timestamp: None, clock_skew: None, warnings: Vec::new()}, b"\x2B\x00\x00\x00{\"account\":\"rust-lang.org\",\"code\":\"123456\"}"; "succeeds for response with hotp code")]
+    #[test_case(& Response::TouchRequired{account: String::from("rust-lang.org"), touch_required: true}, b"\x31\x00\x00\x00{\"account\":\"rust-lang.org\",\"touch_required\":true}"; "succeeds for touch prompt")]
+    #[test_case(& Response::Ambiguous{account: String::from("git"), candidates: vec![String::from("github"), String::from("gitlab")]}, b"\x32\x00\x00\x00{\"account\":\"git\",\"candidates\":[\"github\",\"gitlab\"]}"; "succeeds for ambiguous response")]
+    #[test_case(& Response::Version{protocol: 1, features: vec![String::from("touch")]}, b"\x23\x00\x00\x00{\"protocol\":1,\"features\":[\"touch\"]}"; "succeeds for version response")]
+    #[test_case(& Response::Batch(vec![Reply{id: Some(String::from("1")), response: Response::Ok{ok: true}}, Reply{id: None, response: Response::Error{kind: String::from("no_match"), message: String::from("some error")}}]), b"\x41\x00\x00\x00[{\"id\":\"1\",\"ok\":true},{\"kind\":\"no_match\",\"message\":\"some error\"}]"; "succeeds for batch response")]
+    #[test_case(& Response::Ok{ok: true}, b"\x0B\x00\x00\x00{\"ok\":true}"; "succeeds for acknowledgement")]
+    #[test_case(& Response::AccountList{accounts: vec ! [String::from("rust-lang.org"), String::from("zombo.com")], unparseable: 0}, b"\x2A\x00\x00\x00{\"accounts\":[\"rust-lang.org\",\"zombo.com\"]}"; "succeeds for response with account list")]
+    #[test_case(& Response::CredentialList{credentials: vec![oath::Credential{id: String::from("GitHub:alice"), issuer: Some(String::from("GitHub")), name: String::from("alice"), period: Some(30), digits: Some(6), algorithm: oath::Algorithm::Sha1, oath_type: oath::OathType::Totp, touch: true}]}, b"\x92\x00\x00\x00{\"credentials\":[{\"id\":\"GitHub:alice\",\"issuer\":\"GitHub\",\"name\":\"alice\",\"period\":30,\"digits\":6,\"algorithm\":\"sha1\",\"oath_type\":\"totp\",\"touch\":true}]}"; "succeeds for response with credential list")]
+    #[test_case(& Response::CredentialList{credentials: vec![oath::Credential{id: String::from("counter"), issuer: None, name: String::from("counter"), period: None, digits: None, algorithm: oath::Algorithm::Sha512, oath_type: oath::OathType::Hotp, touch: false}]}, b"\x93\x00\x00\x00{\"credentials\":[{\"id\":\"counter\",\"issuer\":null,\"name\":\"counter\",\"period\":null,\"digits\":null,\"algorithm\":\"sha512\",\"oath_type\":\"hotp\",\"touch\":false}]}"; "succeeds for response with incomplete credential list")]
+    #[test_case(& Response::DeviceInfo{serial: 12345678, version: String::from("5.4.3")}, b"\x25\x00\x00\x00{\"serial\":12345678,\"version\":\"5.4.3\"}"; "succeeds for response with device info")]
+    #[test_case(& Response::DeviceList{devices: vec![Device{serial: 12345678, version: String::from("5.4.3")}]}, b"\x33\x00\x00\x00{\"devices\":[{\"serial\":12345678,\"version\":\"5.4.3\"}]}"; "succeeds for response with device list")]
+    #[test_case(& Response::Error{kind: String::from("no_match"), message: String::from("some error")}, b"\x2A\x00\x00\x00{\"kind\":\"no_match\",\"message\":\"some error\"}"; "succeeds for response with error")]
+    #[test_case(& Response::Enveloped{ok: true, data: Box::new(Response::Resolved{account: String::from("github")})}, b"\x39\x00\x00\x00{\"ok\":true,\"data\":{\"type\":\"Resolved\",\"account\":\"github\"}}"; "succeeds for enveloped response")]
+    #[test_case(& Response::EnvelopedError{ok: false, error: Box::new(Response::Error{kind: String::from("no_match"), message: String::from("some error")})}, b"\x4E\x00\x00\x00{\"ok\":false,\"error\":{\"type\":\"Error\",\"kind\":\"no_match\",\"message\":\"some error\"}}"; "succeeds for enveloped error")]
+    fn serialize_response_succeeds(response: &Response, bytes: &[u8]) {
+        let serialized = serialize_response(response, ByteOrder::Native).unwrap();
+        assert_eq!(
+            bytes, serialized,
+            "assert serialized response equals expected bytes"
+        )
+    }
+
+    // accepts at most three bytes per call and is interrupted before every other call
+    struct TricklingWriter {
+        written: Vec<u8>,
+        interrupt: bool,
+    }
+
+    impl Write for TricklingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let accepted = buf.len().min(3);
+            self.written.extend_from_slice(&buf[..accepted]);
+            Ok(accepted)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_completes_message_on_short_writes() {
+        let mut output = TricklingWriter {
+            written: Vec::new(),
+            interrupt: false,
+        };
+
+        write(
+            &mut output,
+            &Response::Ok { ok: true },
+            ByteOrder::LittleEndian,
+        )
+        .unwrap();
+
+        assert_eq!(
+            b"\x0b\x00\x00\x00{\"ok\":true}".as_slice(),
+            output.written,
+            "assert the whole framed message is written"
+        )
+    }
+
+    #[test]
+    fn serialize_response_sizes_large_message_exactly() {
+        let response = Response::AccountList {
+            accounts: (0..10_000).map(|i| format!("account-{}", i)).collect(),
+            unparseable: 0,
+        };
+        let payload = serde_json::to_vec(&response).unwrap();
+
+        let serialized = serialize_response(&response, ByteOrder::LittleEndian).unwrap();
+
+        assert_eq!(
+            [(payload.len() as u32).to_le_bytes().as_slice(), &payload].concat(),
+            serialized,
+            "assert length prefix is followed by the payload"
+        );
+        assert_eq!(
+            serialized.len(),
+            serialized.capacity(),
+            "assert message is allocated at its final size"
+        )
+    }
+
+    #[test_case(Framing::LengthPrefix(ByteOrder::LittleEndian), 4; "with length prefix")]
+    #[test_case(Framing::JsonLines, 0; "with json lines")]
+    fn write_replaces_oversized_response(framing: Framing, prefix_length: usize) {
+        let response = Response::AccountList {
+            accounts: vec!["x".repeat(MAX_MESSAGE_SIZE)],
+            unparseable: 0,
+        };
+        let mut output = Vec::new();
+
+        framing.write(&mut output, &response).unwrap();
+
+        let written: serde_json::Value = serde_json::from_slice(&output[prefix_length..]).unwrap();
+        assert_eq!(
+            "response_too_large", written["kind"],
+            "assert oversized response is answered with an error instead"
+        )
+    }
+
+    #[test]
+    fn write_keeps_id_and_envelope_of_oversized_response() {
+        let reply = Reply {
+            id: Some(String::from("7")),
+            response: envelop(Response::AccountList {
+                accounts: vec!["x".repeat(MAX_MESSAGE_SIZE)],
+                unparseable: 0,
+            }),
+        };
+        let mut output = Vec::new();
+
+        Framing::JsonLines.write(&mut output, &reply).unwrap();
+
+        let written: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "id": "7",
+                "ok": false,
+                "error": {
+                    "type": "Error",
+                    "kind": "response_too_large",
+                    "message": describe_error(&Error::ResponseTooLarge).1,
+                },
+            }),
+            written,
+            "assert error is sent with the id and in the envelope of the response"
+        )
+    }
+
+    #[test_case(Error::Read, "bad_request"; "maps read error to bad request")]
+    #[test_case(Error::BadJson(String::from("detail")), "bad_request"; "maps bad json to bad request")]
+    #[test_case(Error::EmptySearchTerm, "bad_request"; "maps empty search term to bad request")]
+    #[test_case(Error::StepOffsetOutOfRange, "bad_request"; "maps step offset out of range to bad request")]
+    #[test_case(Error::WindowOutOfRange, "bad_request"; "maps window out of range to bad request")]
+    #[test_case(Error::InvalidUtf8(3), "invalid_utf8"; "maps invalid text to invalid utf8")]
+    #[test_case(Error::Unconfirmed, "confirmation_required"; "maps missing confirmation to confirmation required")]
+    #[test_case(Error::Timeout, "timeout"; "maps timeout to timeout")]
+    #[test_case(Error::ResponseTooLarge, "response_too_large"; "maps oversized response")]
+    #[test_case(Error::Yubikey(yubikey::Error::Busy), "device_busy"; "maps busy reader to device busy")]
+    #[test_case(Error::Oath(oath::Error::NoTouchCredential), "unsupported"; "maps missing touch credential to unsupported")]
+    #[test_case(Error::Oath(oath::Error::TouchPolicyFixed), "unsupported"; "maps fixed touch policy to unsupported")]
+    #[test_case(Error::Oath(oath::Error::TimeOutOfRange), "bad_request"; "maps time out of range to bad request")]
+    #[test_case(Error::Oath(oath::Error::NameTooLong), "bad_request"; "maps overlong name to bad request")]
+    #[test_case(Error::Oath(oath::Error::InvalidDigits), "bad_request"; "maps invalid digits to bad request")]
+    #[test_case(Error::Yubikey(yubikey::Error::SlotNotConfigured), "slot_not_configured"; "maps unconfigured slot")]
+    #[test_case(Error::Yubikey(yubikey::Error::Removed), "device_removed"; "maps removed card to device removed")]
+    #[test_case(Error::Yubikey(yubikey::Error::NoDeviceForTransport), "no_device_for_transport"; "maps missing device of transport")]
+    #[test_case(Error::Yubikey(yubikey::Error::NoDevice), "no_device"; "maps missing device to no device")]
+    #[test_case(Error::Yubikey(yubikey::Error::Connection(None)), "device_error"; "maps connection error to device error")]
+    #[test_case(Error::Yubikey(yubikey::Error::Transmission(None)), "device_error"; "maps transmission error to device error")]
+    #[test_case(Error::Yubikey(yubikey::Error::NoMatchingSerial), "no_matching_device"; "maps unknown serial to no matching device")]
+    #[test_case(Error::Yubikey(yubikey::Error::AppletNotFound), "applet_not_found"; "maps missing application")]
+    #[test_case(Error::Yubikey(yubikey::Error::ChallengeTooLong), "bad_request"; "maps long challenge to bad request")]
+    #[test_case(Error::Oath(oath::Error::Yubikey), "oath_failure"; "maps oath error to oath failure")]
+    #[test_case(Error::Oath(oath::Error::NoMatchingCredential), "no_match"; "maps missing credential to no match")]
+    #[test_case(Error::Oath(oath::Error::CredentialExists), "already_exists"; "maps existing credential to already exists")]
+    #[test_case(Error::Oath(oath::Error::AuthRequired), "locked"; "maps required authentication to locked")]
+    #[test_case(Error::Oath(oath::Error::InvalidSecret), "invalid_secret"; "maps invalid secret to invalid secret")]
+    #[test_case(Error::Oath(oath::Error::Rejected), "device_rejected"; "maps rejection to device rejected")]
+    #[test_case(Error::Oath(oath::Error::WrongPassword), "wrong_password"; "maps wrong password to wrong password")]
+    #[test_case(Error::Oath(oath::Error::TouchTimeout), "touch_timeout"; "maps touch timeout to touch timeout")]
+    fn error_response_has_kind(error: Error, kind: &str) {
+        let serialized = serde_json::to_value(error_response(&error)).unwrap();
+        assert_eq!(
+            kind, serialized["kind"],
+            "assert serialized error response has expected kind"
+        )
+    }
+
+    #[test]
+    fn log_message_contains_status() {
+        let error = Error::Yubikey(yubikey::Error::Connection(Some(pcsc::Error::RemovedCard)));
+
+        assert_eq!(
+            "Could not connect to YubiKey (PC/SC status RemovedCard, 0x80100069)",
+            log_message(&error, describe_error(&error).1),
+            "assert PC/SC status is logged"
+        );
+        assert_eq!(
+            "Could not connect to YubiKey",
+            serde_json::to_value(error_response(&error)).unwrap()["message"],
+            "assert PC/SC status is not answered"
+        )
+    }
+
+    #[test]
+    fn log_message_leaves_out_values_of_the_message() {
+        let error = deserialize_request(b"{\"type\":\"Ping\",\"password\":1234}")
+            .unwrap_err()
+            .error;
+        let message = match error_response(&error) {
+            Response::Error { message, .. } => message,
+            response => panic!("unexpected response {:?}", response),
+        };
+
+        assert!(
+            message.contains("1234"),
+            "assert detail is answered, got {}",
+            message
+        );
+        assert_eq!(
+            "Could not read request",
+            log_message(&error, &message),
+            "assert detail quoting the password is not logged"
+        )
+    }
+
+    #[test_case(287_082, 6, "287082"; "formats six digits")]
+    #[test_case(4_287_082, 7, "4287082"; "formats seven digits")]
+    #[test_case(94_287_082, 8, "94287082"; "formats eight digits")]
+    #[test_case(12_345, 8, "00012345"; "pads to eight digits")]
+    fn format_code_succeeds(value: u32, digits: u8, expected: &str) {
+        assert_eq!(
+            expected,
+            format_code(&oath::Code {
+                value,
+                digits,
+                steam: false,
+                period: None
+            }),
+            "assert code is formatted to the digits of the credential"
+        )
+    }
+
+    #[test]
+    fn host_info_reports_package_version() {
+        assert!(
+            matches!(host_info(), Response::HostInfo { version, .. } if version == env!("CARGO_PKG_VERSION")),
+            "assert version of the package is reported"
+        )
+    }
+
+    #[test]
+    fn code_from_secret_calculates_rfc_vector() {
+        // the RFC 6238 SHA1 seed "12345678901234567890"
+        assert_eq!(
+            Response::CodeFromSecret {
+                code: String::from("94287082"),
+                valid_for: Some(1),
+            },
+            code_from_secret(
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ",
+                oath::Algorithm::Sha1,
+                8,
+                30,
+                59
+            ),
+            "assert code is calculated from the secret"
+        )
+    }
+
+    #[test_case("GEZDGNBVGY3TQOJQ", 10, 30, "bad_request"; "fails on unsupported digits")]
+    #[test_case("GEZDGNBVGY3TQOJQ", 6, 0, "bad_request"; "fails on zero period")]
+    #[test_case("GEZDGNBVGY3TQOJ1", 6, 30, "invalid_secret"; "fails on invalid base32")]
+    fn code_from_secret_rejects_invalid_request(secret: &str, digits: u8, period: u64, kind: &str) {
+        let response = code_from_secret(secret, oath::Algorithm::Sha1, digits, period, 59);
+
+        assert!(
+            matches!(response, Response::Error { kind: k, .. } if k == kind),
+            "assert invalid parameters are refused"
+        )
+    }
+
+    #[test]
+    fn hotp_at_calculates_rfc_vector() {
+        // the RFC 4226 seed "12345678901234567890"
+        assert_eq!(
+            Response::CodeFromSecret {
+                code: String::from("969429"),
+                valid_for: None,
+            },
+            hotp_at(
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ",
+                oath::Algorithm::Sha1,
+                6,
+                3
+            ),
+            "assert code is calculated at the counter"
+        )
+    }
+
+    #[test]
+    fn format_code_encodes_steam_code() {
+        // truncated HMAC-SHA1 of the RFC 6238 secret at timestamp 59
+        let code = oath::Code {
+            value: 1_094_287_082,
+            digits: 5,
+            steam: true,
+            period: Some(30),
+        };
+        assert_eq!(
+            "PV9M4",
+            format_code(&code),
+            "assert steam code uses the steam alphabet"
+        )
+    }
+
+    #[test_case(1_650_000_000, 30, 30; "is a full period at window start")]
+    #[test_case(1_650_000_023, 30, 7; "counts down mid-window")]
+    #[test_case(1_650_000_029, 30, 1; "is one second at window end")]
+    #[test_case(1_650_000_029, 60, 31; "counts down a longer period")]
+    fn valid_for_succeeds(timestamp: u64, period: u64, expected: u64) {
+        assert_eq!(
+            expected,
+            valid_for(timestamp, period),
+            "assert remaining validity equals expected seconds"
+        )
+    }
+
+    #[test_case(
+    b"\x1B\x00\x00\x00{\"account\":\"rust-lang.org\"}",
+    b"{\"account\":\"rust-lang.org\"}";
+    "succeeds for correct input length"
+    )]
+    #[test_case(
+    b"\x1B\x00\x00\x00{\"account\":\"rust-lang.org\"}herearesomemorebytes",
+    b"{\"account\":\"rust-lang.org\"}";
+    "succeeds for additional bytes after input"
+    )]
+    fn read_input_succeeds(input_bytes: &[u8], output_bytes: &[u8]) {
+        let buffer = input_bytes.to_vec();
+        let read_bytes = read_input(&mut buffer.as_slice(), ByteOrder::Native)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            output_bytes, read_bytes,
+            "assert read bytes equal expected bytes"
+        )
+    }
+
+    #[test]
+    fn read_input_signals_eof_on_empty_input() {
+        assert!(
+            matches!(read_input(&mut b"".as_slice(), ByteOrder::Native), Ok(None)),
+            "assert reading empty input signals end of input"
+        )
+    }
+
+    #[test_case(b"\x1B\x00\x00\x00{\"account\":\"rust.org\"}"; "fails for too short input")]
+    #[test_case(b"\x1B\x00"; "fails for truncated length prefix")]
+    fn read_input_fails(input_bytes: &[u8]) {
+        let buffer = input_bytes.to_vec();
+        assert!(
+            matches!(
+                read_input(&mut buffer.as_slice(), ByteOrder::Native),
+                Err(Error::Read)
+            ),
+            "assert reading input fails"
+        )
+    }
+
+    fn message_of_length(length: usize) -> Vec<u8> {
+        let prefix = u32::try_from(length).unwrap().to_ne_bytes();
+        [&prefix[..], &vec![b' '; length]].concat()
+    }
+
+    #[test_case(b"{\"type\":\"Ping\"}", 15, Frame { length: 15, received: 15 }; "counts complete message")]
+    #[test_case(b"{\"type\":", 15, Frame { length: 15, received: 8 }; "counts message cut short")]
+    #[test_case(b"{\"type\":\"Ping\"}{}", 15, Frame { length: 15, received: 15 }; "counts only the length of the prefix")]
+    fn read_body_counts_received_bytes(input: &[u8], length: usize, expected: Frame) {
+        let (_, frame) = read_body(&mut &input[..], length).unwrap();
+
+        assert_eq!(expected, frame, "assert received bytes are counted")
+    }
+
+    #[test]
+    fn read_input_accepts_maximum_message_size() {
+        let input = message_of_length(MAX_MESSAGE_SIZE);
+
+        assert_eq!(
+            MAX_MESSAGE_SIZE,
+            read_input(&mut input.as_slice(), ByteOrder::Native)
+                .unwrap()
+                .unwrap()
+                .len(),
+            "assert message of maximum size is read"
+        )
+    }
+
+    #[test]
+    fn read_input_rejects_oversized_message() {
+        let input = message_of_length(MAX_MESSAGE_SIZE + 1);
+
+        assert!(
+            matches!(
+                read_input(&mut input.as_slice(), ByteOrder::Native),
+                Err(Error::Read)
+            ),
+            "assert message above maximum size is rejected"
+        )
+    }
+
+    fn answer_with_account(message: &Message, _: &mut dyn FnMut(&Response)) -> Response {
+        match &message.request {
+            Request::Code { account, .. } => Response::Code {
+                account: account.to_owned(),
+                code: String::from("123456"),
+                valid_for: Some(30),
+                next_code: None,
+                next_valid_for: None,
+                timestamp: None,
+                clock_skew: None,
+                warnings: Vec::new(),
+            },
+            _ => Response::AccountList {
+                accounts: vec![],
+                unparseable: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn run_loop_answers_every_request() {
+        let input = b"\x21\x00\x00\x00{\"type\":\"Code\",\"account\":\"first\"}\
+                      \x22\x00\x00\x00{\"type\":\"Code\",\"account\":\"second\"}";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::LengthPrefix(ByteOrder::Native),
+            answer_with_account,
+        )
+        .unwrap();
+
+        let expected = [
+            b"\x32\x00\x00\x00{\"account\":\"first\",\"code\":\"123456\",\"valid_for\":30}"
+                .as_slice(),
+            b"\x33\x00\x00\x00{\"account\":\"second\",\"code\":\"123456\",\"valid_for\":30}"
+                .as_slice(),
+        ]
+        .concat();
+        assert_eq!(
+            expected, output,
+            "assert one framed response is written per request"
+        )
+    }
+
+    #[test]
+    fn run_loop_ends_after_close() {
+        let input = b"\x0f\x00\x00\x00{\"type\":\"Ping\"}\
+                      \x10\x00\x00\x00{\"type\":\"Close\"}\
+                      \x0f\x00\x00\x00{\"type\":\"Ping\"}";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::LengthPrefix(ByteOrder::Native),
+            handle_request,
+        )
+        .unwrap();
+
+        assert_eq!(
+            b"\x0b\x00\x00\x00{\"ok\":true}\x0b\x00\x00\x00{\"ok\":true}".as_slice(),
+            output,
+            "assert close is answered and no request after it"
+        )
+    }
+
+    #[test]
+    fn run_loop_continues_after_bad_request() {
+        let input = b"\x09\x00\x00\x00{\"type\":}\
+                      \x21\x00\x00\x00{\"type\":\"Code\",\"account\":\"first\"}";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::LengthPrefix(ByteOrder::Native),
+            answer_with_account,
+        )
+        .unwrap();
+
+        let expected = [
+            b"\x5C\x00\x00\x00{\"kind\":\"bad_request\",\"message\":\"Could not read request: expected value at line 1 column 9\"}"
+                .as_slice(),
+            b"\x32\x00\x00\x00{\"account\":\"first\",\"code\":\"123456\",\"valid_for\":30}"
+                .as_slice(),
+        ]
+        .concat();
+        assert_eq!(
+            expected, output,
+            "assert invalid request is answered and the next one is handled"
+        )
+    }
+
+    #[test]
+    fn run_loop_continues_after_empty_message() {
+        let input = b"\x00\x00\x00\x00\
+                      \x21\x00\x00\x00{\"type\":\"Code\",\"account\":\"first\"}";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::LengthPrefix(ByteOrder::Native),
+            answer_with_account,
+        )
+        .unwrap();
+
+        let expected = [
+            b"\x67\x00\x00\x00{\"kind\":\"bad_request\",\"message\":\"Could not read request: EOF while parsing a value at line 1 column 0\"}"
+                .as_slice(),
+            b"\x32\x00\x00\x00{\"account\":\"first\",\"code\":\"123456\",\"valid_for\":30}"
+                .as_slice(),
+        ]
+        .concat();
+        assert_eq!(
+            expected, output,
+            "assert empty message is answered as bad request and the session continues"
+        )
+    }
+
+    #[test]
+    fn run_loop_ends_on_truncated_message() {
+        let input = b"\x21\x00\x00\x00{\"type\":\"Code\"";
+        let mut output: Vec<u8> = Vec::new();
+
+        assert!(
+            matches!(
+                run_loop(
+                    &mut input.as_slice(),
+                    &mut output,
+                    Framing::LengthPrefix(ByteOrder::Native),
+                    answer_with_account
+                ),
+                Err(Error::Read)
+            ),
+            "assert framing error ends the loop"
+        );
+        assert!(output.is_empty(), "assert nothing is answered")
+    }
+
+    #[test]
+    fn serve_answers_requests_from_stream() {
+        let input = Cursor::new(b"\x18\x00\x00\x00{\"type\":\"Ping\",\"id\":\"1\"}".to_vec());
+        let mut output = Cursor::new(Vec::new());
+
+        serve(input, &mut output).unwrap();
+
+        assert_eq!(
+            b"\x14\x00\x00\x00{\"id\":\"1\",\"ok\":true}".as_slice(),
+            output.get_ref().as_slice(),
+            "assert request is read, handled and answered on the given streams"
+        )
+    }
+
+    #[test]
+    fn serve_echoes_multi_byte_payload() {
+        let request = "{\"type\":\"Echo\",\"payload\":\"grüße ✓ 🔑\"}";
+        let mut input = (request.len() as u32).to_le_bytes().to_vec();
+        input.extend_from_slice(request.as_bytes());
+        let mut output = Cursor::new(Vec::new());
+
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let response = "{\"payload\":\"grüße ✓ 🔑\"}";
+        let mut expected = (response.len() as u32).to_le_bytes().to_vec();
+        expected.extend_from_slice(response.as_bytes());
+        assert_eq!(
+            expected,
+            output.into_inner(),
+            "assert payload is answered byte for byte with its length in bytes"
+        )
+    }
+
+    struct DelayedReader {
+        delay: Duration,
+        input: &'static [u8],
+    }
+
+    impl Read for DelayedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            self.input.read(buf)
+        }
+    }
+
+    #[test]
+    fn serve_ends_when_idle() {
+        let input = DelayedReader {
+            delay: Duration::from_millis(500),
+            input: b"\x0f\x00\x00\x00{\"type\":\"Ping\"}",
+        };
+        let mut output: Vec<u8> = Vec::new();
+
+        serve(
+            IdleReader::new(input, Duration::from_millis(20)),
+            &mut output,
+        )
+        .unwrap();
+
+        assert!(
+            output.is_empty(),
+            "assert session ends before the late request is answered"
+        )
+    }
+
+    #[test]
+    fn serve_answers_request_before_idle_timeout() {
+        let input = DelayedReader {
+            delay: Duration::ZERO,
+            input: b"\x0f\x00\x00\x00{\"type\":\"Ping\"}",
+        };
+        let mut output: Vec<u8> = Vec::new();
+
+        serve(IdleReader::new(input, Duration::from_secs(5)), &mut output).unwrap();
+
+        assert_eq!(
+            b"\x0b\x00\x00\x00{\"ok\":true}".as_slice(),
+            output,
+            "assert request arriving in time is answered"
+        )
+    }
+
+    #[test]
+    fn serve_jsonl_answers_each_line() {
+        let input =
+            b"{\"type\":\"Ping\",\"id\":\"1\"}\n\n{\"type\":\"Ping\",\"id\":\"2\"}\r\n".as_slice();
+        let mut output: Vec<u8> = Vec::new();
+
+        serve_jsonl(input, &mut output).unwrap();
+
+        assert_eq!(
+            b"{\"id\":\"1\",\"ok\":true}\n{\"id\":\"2\",\"ok\":true}\n".as_slice(),
+            output,
+            "assert every line is answered with a line"
+        )
+    }
+
+    #[test_case(b"{\"type\":\"Ping\",\"envelope\":true}", b"{\"ok\":true,\"data\":{\"type\":\"Ok\",\"ok\":true}}\n"; "wraps success")]
+    #[test_case(b"{\"type\":\"Reset\",\"envelope\":true}", b"{\"ok\":false,\"error\":{\"type\":\"Error\",\"kind\":\"confirmation_required\",\"message\":\"Request has to be confirmed\"}}\n"; "wraps error")]
+    fn serve_jsonl_envelops_on_request(input: &[u8], expected: &[u8]) {
+        let mut output: Vec<u8> = Vec::new();
+
+        serve_jsonl(input, &mut output).unwrap();
+
+        assert_eq!(
+            expected,
+            output.as_slice(),
+            "assert response is wrapped in an envelope"
+        )
+    }
+
+    fn credential() -> oath::Credential {
+        oath::Credential {
+            id: String::from("GitHub:alice"),
+            issuer: Some(String::from("GitHub")),
+            name: String::from("alice"),
+            period: Some(30),
+            digits: Some(6),
+            algorithm: oath::Algorithm::Sha1,
+            oath_type: oath::OathType::Totp,
+            touch: false,
+        }
+    }
+
+    #[test_case(Response::Code { account: String::from("github"), code: String::from("287082"), valid_for: Some(1), next_code: Some(String::from("751162")), next_valid_for: Some(31), timestamp: Some(59), clock_skew: Some(-2), warnings: vec![String::from("matched by fuzzy search")] }; "code")]
+    #[test_case(Response::CodeFromSecret { code: String::from("287082"), valid_for: Some(1) }; "code from secret")]
+    #[test_case(Response::TouchRequired { account: String::from("vault"), touch_required: true }; "touch required")]
+    #[test_case(Response::TouchWaiting { account: String::from("vault"), elapsed_ms: 1000 }; "touch waiting")]
+    #[test_case(Response::Ambiguous { account: String::from("git"), candidates: vec![String::from("github"), String::from("gitlab")] }; "ambiguous")]
+    #[test_case(Response::Ok { ok: true }; "ok")]
+    #[test_case(Response::Echo { payload: String::from("grüße") }; "echo")]
+    #[test_case(Response::AccountList { accounts: vec![String::from("github")], unparseable: 1 }; "account list")]
+    #[test_case(Response::CodeAll { codes: vec![AccountCode { account: String::from("github"), code: None, valid_for: None, touch_required: true }] }; "code all")]
+    #[test_case(Response::CodeMany { codes: vec![Response::Ok { ok: true }] }; "code many")]
+    #[test_case(Response::CredentialList { credentials: vec![credential()] }; "credential list")]
+    #[test_case(Response::CredentialInfo(credential()); "credential info")]
+    #[test_case(Response::Export { credentials: vec![String::from("otpauth://totp/alice")] }; "export")]
+    #[test_case(Response::Capacity { used: 3, max: Some(64) }; "capacity")]
+    #[test_case(Response::Latency { millis: 42 }; "latency")]
+    #[test_case(Response::ImportResult { results: vec![ImportItem { uri: String::from("otpauth://totp/alice"), response: Response::Ok { ok: true } }] }; "import result")]
+    #[test_case(Response::MigrationResult { results: vec![MigrationItem { account: String::from("alice"), response: Response::Ok { ok: true } }] }; "migration result")]
+    #[test_case(Response::DeviceInfo { serial: 12345678, version: String::from("5.4.3") }; "device info")]
+    #[test_case(Response::DeviceList { devices: vec![Device { serial: 12345678, version: String::from("5.4.3") }] }; "device list")]
+    #[test_case(Response::OathInfo { version: Some(String::from("5.4.3")), oath_locked: true }; "oath info")]
+    #[test_case(Response::Hmac { response_hex: String::from("c0ffee") }; "hmac")]
+    #[test_case(Response::Version { protocol: 1, features: vec![String::from("hotp")] }; "version")]
+    #[test_case(Response::HostInfo { version: String::from("0.1.0"), git_sha: Some(String::from("b20e850")), build_profile: String::from("release") }; "host info")]
+    #[test_case(Response::Error { kind: String::from("no_match"), message: String::from("some error") }; "error")]
+    #[test_case(Response::Verify { account: String::from("github"), valid: true }; "verify")]
+    #[test_case(Response::Resolved { account: String::from("github") }; "resolved")]
+    #[test_case(Response::Exists { account: String::from("github"), exists: true }; "exists")]
+    fn envelope_round_trips_tagged_response(response: Response) {
+        let kind = response.kind();
+        let enveloped = envelop(response);
+
+        let serialized = serde_json::to_value(&enveloped).unwrap();
+        let wrapped = serialized.get("data").or(serialized.get("error")).unwrap();
+
+        assert_eq!(
+            Some(kind),
+            wrapped["type"].as_str(),
+            "assert wrapped response is tagged with its variant"
+        );
+        assert_eq!(
+            enveloped,
+            serde_json::from_value(serialized).unwrap(),
+            "assert tagged response deserializes to the same variant"
+        )
+    }
+
+    #[test_case(serde_json::json!({"type": "Exists", "account": "github"}); "fails on missing field of variant")]
+    #[test_case(serde_json::json!({"account": "github"}); "fails without type")]
+    fn tagged_rejects_response_not_matching_type(response: serde_json::Value) {
+        assert!(
+            tagged::deserialize(response).is_err(),
+            "assert response is not read as another variant than its type"
+        )
+    }
+
+    #[test]
+    fn serve_once_writes_plain_json() {
+        let message = Message {
+            request: Request::Ping,
+            password: None,
+            serial: None,
+            transport: yubikey::Transport::Any,
+            id: Some(String::from("1")),
+            envelope: false,
+        };
+        let mut output: Vec<u8> = Vec::new();
+
+        serve_once(Ok(message), &mut output).unwrap();
+
+        assert_eq!(
+            b"{\"id\":\"1\",\"ok\":true}\n".as_slice(),
+            output,
+            "assert response is written without length prefix"
+        )
+    }
+
+    fn no_cache() -> ListingCache {
+        ListingCache::new(Duration::ZERO)
+    }
+
+    const TIMEOUTS: Timeouts = Timeouts {
+        request: DEFAULT_REQUEST_TIMEOUT,
+        touch: DEFAULT_TOUCH_TIMEOUT,
+        heartbeat: DEFAULT_HEARTBEAT,
+    };
+
+    #[test]
+    fn read_accounts_list_reports_locked_application() {
+        let response = read_accounts_list(
+            || Ok(MockCard::new(&[b"\x69\x82"])),
+            ListOptions::default(),
+            DEFAULT_REQUEST_TIMEOUT,
+            no_cache().of(DeviceKey::default()),
+        );
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "locked"),
+            "assert application locked without password is reported as locked"
+        )
+    }
+
+    #[test]
+    fn read_accounts_list_reports_wrong_password() {
+        let response = read_accounts_list(
+            || Err::<MockCard, _>(Error::Oath(oath::Error::WrongPassword)),
+            ListOptions::default(),
+            DEFAULT_REQUEST_TIMEOUT,
+            no_cache().of(DeviceKey::default()),
+        );
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "wrong_password"),
+            "assert failed unlock is reported as wrong password"
+        )
+    }
+
+    #[test]
+    fn read_device_info_formats_version() {
+        let yubikey = MockCard::new(&[
+            b"\x05\x04\x03\x07\x00\x00\x90\x00",
+            b"\x00\xbc\x61\x4e\x90\x00",
+            b"\x90\x00",
+        ]);
+        assert_eq!(
+            Response::DeviceInfo {
+                serial: 12345678,
+                version: String::from("5.4.3")
+            },
+            read_device_info(|| Ok(yubikey)),
+            "assert device info response contains serial and dotted version"
+        )
+    }
+
+    #[test]
+    fn challenge_response_formats_response_as_hex() {
+        let yubikey = MockCard::new(&[
+            b"\x05\x04\x03\x07\x00\x00\x90\x00",
+            b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\xaa\xbb\xcc\xdd\xee\xff\x10\x20\x30\x40\x90\x00",
+        ]);
+
+        assert_eq!(
+            Response::Hmac {
+                response_hex: String::from("00010203040506070809aabbccddeeff10203040")
+            },
+            challenge_response(|| Ok(yubikey), 1, "C0FFEE"),
+            "assert HMAC is answered as hex"
+        )
+    }
+
+    #[test_case(3, "c0ffee"; "rejects unknown slot")]
+    #[test_case(1, "c0ffe"; "rejects odd number of digits")]
+    #[test_case(1, "coffee"; "rejects illegal digits")]
+    fn challenge_response_rejects_invalid_request(slot: u8, challenge_hex: &str) {
+        let response = challenge_response(
+            || -> Result<MockCard, Error> { panic!("YubiKey must not be accessed") },
+            slot,
+            challenge_hex,
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "bad_request"),
+            "assert invalid request is refused"
+        )
+    }
+
+    #[test]
+    fn challenge_response_rejects_long_challenge() {
+        let challenge_hex = "00".repeat(yubikey::MAX_CHALLENGE_LENGTH + 1);
+
+        let response = challenge_response(
+            || -> Result<MockCard, Error> { panic!("YubiKey must not be accessed") },
+            1,
+            &challenge_hex,
+        );
+
+        assert_eq!(
+            error_response(&Error::InvalidChallenge),
+            response,
+            "assert challenge above the maximum length is refused as a bad request"
+        )
+    }
+
+    #[test]
+    fn set_touch_refuses_without_sending() {
+        // a command sent to the card would fail with a transmission error instead
+        let response = set_touch(|| Ok(MockCard::new(&[])));
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "unsupported"),
+            "assert touch policy change is refused as unsupported"
+        )
+    }
+
+    #[test]
+    fn oath_info_formats_applet_version() {
+        assert_eq!(
+            Response::OathInfo {
+                version: Some(String::from("5.4.3")),
+                oath_locked: false,
+            },
+            oath_info(b"\x79\x03\x05\x04\x03\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x90\x00"),
+            "assert applet version is answered as dotted version"
+        )
+    }
+
+    #[test]
+    fn oath_info_reports_password_protection() {
+        let response = oath_info(
+            b"\x79\x03\x05\x04\x03\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x74\x08\x11\x12\x13\x14\x15\x16\x17\x18\x7b\x01\x01\x90\x00",
+        );
+
+        assert!(
+            matches!(
+                response,
+                Response::OathInfo {
+                    oath_locked: true,
+                    ..
+                }
+            ),
+            "assert challenge in the selection marks the application as locked"
+        )
+    }
+
+    #[test]
+    fn measure_latency_times_selection() {
+        let yubikey = MockCard::new(&[b"\x79\x03\x05\x04\x03\x90\x00"]);
+        let start = Instant::now();
+        let readings = Cell::new(0);
+        let clock = || {
+            readings.set(readings.get() + 1);
+            start + Duration::from_millis(42) * (readings.get() - 1)
+        };
+
+        assert_eq!(
+            Response::Latency { millis: 42 },
+            measure_latency(|| Ok(yubikey), yubikey::OATH_AID, clock),
+            "assert time between sending and receiving the selection is answered"
+        )
+    }
+
+    #[test]
+    fn read_touch_required_list_keeps_touch_credentials() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x21GitHub:alice\x72\x06\x21Vault\x72\x05\x21Bank\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x71\x05Vault\x7c\x01\x06\x71\x04Bank\x7c\x01\x06\x90\x00",
+        ]);
+
+        assert_eq!(
+            Response::AccountList {
+                accounts: vec![String::from("Vault"), String::from("Bank")],
+                unparseable: 0,
+            },
+            read_touch_required_list(|| Ok(yubikey), 59),
+            "assert only credentials requiring a touch are listed"
+        )
+    }
+
+    #[test]
+    fn read_credential_info_returns_metadata() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x21GitHub:alice\x72\x06\x21Vault\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x71\x05Vault\x7c\x01\x06\x90\x00",
+        ]);
+
+        assert_eq!(
+            serde_json::json!({
+                "id": "GitHub:alice",
+                "issuer": "GitHub",
+                "name": "alice",
+                "period": 30,
+                "digits": 6,
+                "algorithm": "sha1",
+                "oath_type": "totp",
+                "touch": false,
+            }),
+            serde_json::to_value(read_credential_info(|| Ok(yubikey), "GitHub:alice", 59)).unwrap(),
+            "assert metadata of the credential is answered"
+        )
+    }
+
+    #[test]
+    fn read_credential_info_fails_without_exact_match() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x21GitHub:alice\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+
+        assert!(
+            matches!(read_credential_info(|| Ok(yubikey), "github", 59), Response::Error { kind, .. } if kind == "no_match"),
+            "assert a fuzzy match is not enough"
+        )
+    }
+
+    #[test]
+    fn read_capacity_counts_credentials() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x07\x21github\x72\x07\x21gitlab\x72\x06\x21Vault\x90\x00",
+            b"\x05\x07\x01\x07\x00\x00\x90\x00",
+            b"\x00\xbc\x61\x4e\x90\x00",
+        ]);
+
+        assert_eq!(
+            Response::Capacity {
+                used: 3,
+                max: Some(64)
+            },
+            read_capacity(|| Ok(yubikey)),
+            "assert listed credentials are counted against the slots of the firmware"
+        )
+    }
+
+    #[test]
+    fn read_capacity_without_version_has_no_max() {
+        let yubikey = MockCard::new(&[b"\x72\x07\x21github\x90\x00", b"\x6d\x00"]);
+
+        assert_eq!(
+            Response::Capacity { used: 1, max: None },
+            read_capacity(|| Ok(yubikey)),
+            "assert unknown version does not fail the request"
+        )
+    }
+
+    #[test]
+    fn require_touch_times_out_without_touch() {
+        let yubikey = MockCard::with_delay(
+            &[
+                VAULT_LIST,
+                VAULT_TOUCH_CODES,
+                b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+            ],
+            Duration::from_millis(100),
+        );
+
+        let response = require_touch(
+            || Ok(yubikey),
+            59,
+            Timeouts {
+                request: DEFAULT_REQUEST_TIMEOUT,
+                touch: Duration::from_millis(10),
+                heartbeat: DEFAULT_HEARTBEAT,
+            },
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "touch_timeout"),
+            "assert missing touch is reported as touch timeout"
+        )
+    }
+
+    #[test]
+    fn require_touch_acknowledges_touch() {
+        let yubikey = vault_card(&[VAULT_TOUCH_CODES, b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00"]);
+
+        assert_eq!(
+            Response::Ok { ok: true },
+            require_touch(|| Ok(yubikey), 59, TIMEOUTS),
+            "assert touch is acknowledged"
+        )
+    }
+
+    #[test]
+    fn read_otp_times_out_waiting_for_touch() {
+        let yubikey = MockCard::with_delay(
+            &[
+                VAULT_LIST,
+                VAULT_TOUCH_CODES,
+                b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+            ],
+            Duration::from_millis(100),
+        );
+        let mut notifications = Vec::new();
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            Timeouts {
+                request: DEFAULT_REQUEST_TIMEOUT,
+                touch: Duration::from_millis(10),
+                heartbeat: DEFAULT_HEARTBEAT,
+            },
+            no_cache().of(DeviceKey::default()),
+            &mut |r| notifications.push(serde_json::to_value(r).unwrap()),
+        );
+
+        assert_eq!(
+            vec![serde_json::json!({"account": "vault", "touch_required": true})],
+            notifications,
+            "assert touch prompt is sent before waiting"
+        );
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "touch_timeout"),
+            "assert missing touch is reported as touch timeout"
+        )
+    }
+
+    #[test]
+    fn read_otp_reports_ambiguous_search_term() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x07\x21github\x72\x07\x21gitlab\x90\x00",
+            b"\x71\x06github\x76\x05\x06\x41\x39\x7e\xea\x71\x06gitlab\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "git",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert_eq!(
+            Response::Ambiguous {
+                account: String::from("git"),
+                candidates: vec![String::from("github"), String::from("gitlab")]
+            },
+            response,
+            "assert candidates are returned instead of a code"
+        )
+    }
+
+    #[test_case(&[VAULT_LIST, VAULT_CODES], "no_match"; "reports unmatched search term as no match")]
+    #[test_case(&[], "oath_failure"; "reports failed communication as oath failure")]
+    fn read_otp_reports_error_kind(responses: &[&[u8]], expected_kind: &str) {
+        let yubikey = MockCard::new(responses);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "github",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == expected_kind),
+            "assert error kind distinguishes missing credentials from device failures"
+        )
+    }
+
+    #[test]
+    fn handle_request_reports_protocol_version() {
+        let message = Message {
+            request: Request::Version,
+            password: None,
+            serial: None,
+            transport: yubikey::Transport::Any,
+            id: None,
+            envelope: false,
+        };
+
+        match handle_request(&message, &mut |_| {}) {
+            Response::Version { protocol, features } => {
+                assert_eq!(PROTOCOL_VERSION, protocol, "assert protocol version");
+                assert!(!features.is_empty(), "assert features are listed")
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[test_case(b"{\"type\":\"CodeExact\",\"id\":\"7\"}", Some("7"); "keeps id on missing field")]
+    #[test_case(b"{\"type\":\"Code\",\"account\":22,\"id\":\"7\"}", Some("7"); "keeps id on wrong type")]
+    #[test_case(b"{\"type\":\"Bogus\",\"id\":\"7\"}", Some("7"); "keeps id on unknown type")]
+    #[test_case(b"{\"type\":\"Code\",\"id\":7}", None; "drops id that is no string")]
+    fn deserialize_request_keeps_id_of_rejected_message(bytes: &[u8], expected: Option<&str>) {
+        assert!(
+            matches!(deserialize_request(bytes), Err(Rejected { id, .. }) if id.as_deref() == expected),
+            "assert id is read from the rejected message"
+        )
+    }
+
+    #[test]
+    fn run_loop_answers_rejected_messages_with_their_id() {
+        let input = b"{\"type\":\"Code\",\"id\":\"7\"}\n\
+                      {\"type\":\"Code\",\"account\":22,\"id\":\"8\"}\n\
+                      [{\"type\":\"Bogus\",\"id\":\"9\"}]\n";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::JsonLines,
+            handle_request,
+        )
+        .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(
+            lines[0].starts_with("{\"id\":\"7\",\"kind\":\"bad_request\""),
+            "assert refused request is answered with its id, got {}",
+            lines[0]
+        );
+        assert!(
+            lines[1].starts_with("{\"id\":\"8\",\"kind\":\"bad_request\""),
+            "assert unreadable message is answered with its id, got {}",
+            lines[1]
+        );
+        assert!(
+            lines[2].starts_with("[{\"id\":\"9\",\"kind\":\"bad_request\""),
+            "assert rejected message of a batch is answered with its id, got {}",
+            lines[2]
+        )
+    }
+
+    #[test]
+    fn deserialize_request_reads_batch() {
+        let input = deserialize_request(b"[{\"type\":\"AccountList\"},{\"type\":\"Unknown\"}]");
+
+        match input {
+            Ok(Input::Batch(messages)) => {
+                assert_eq!(2, messages.len(), "assert every message is read");
+                assert!(
+                    matches!(
+                        &messages[0],
+                        Ok(Message {
+                            request: Request::AccountList { .. },
+                            ..
+                        })
+                    ),
+                    "assert valid message is read"
+                );
+                assert!(
+                    matches!(
+                        messages[1],
+                        Err(Rejected {
+                            error: Error::UnknownRequestType(_),
+                            ..
+                        })
+                    ),
+                    "assert invalid message fails in its slot"
+                )
+            }
+            input => panic!("unexpected input {:?}", input),
+        }
+    }
+
+    #[test]
+    fn run_loop_answers_batch_in_order() {
+        let input =
+            b"\x38\x00\x00\x00[{\"type\":\"Code\",\"account\":\"first\"},{\"type\":\"CodeExact\"}]";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::LengthPrefix(ByteOrder::Native),
+            answer_with_account,
+        )
+        .unwrap();
+
+        assert_eq!(
+            b"\x87\x00\x00\x00[{\"account\":\"first\",\"code\":\"123456\",\"valid_for\":30},{\"kind\":\"bad_request\",\"message\":\"Could not read request: missing field `account`\"}]".as_slice(),
+            output,
+            "assert batch is answered with one response per message"
+        )
+    }
+
+    #[test]
+    fn run_loop_echoes_id() {
+        let input = b"\x2A\x00\x00\x00{\"type\":\"Code\",\"account\":\"first\",\"id\":\"7\"}";
+        let mut output: Vec<u8> = Vec::new();
+
+        run_loop(
+            &mut input.as_slice(),
+            &mut output,
+            Framing::LengthPrefix(ByteOrder::Native),
+            answer_with_account,
+        )
+        .unwrap();
+
+        assert_eq!(
+            b"\x3B\x00\x00\x00{\"id\":\"7\",\"account\":\"first\",\"code\":\"123456\",\"valid_for\":30}".as_slice(),
+            output,
+            "assert id is copied into the response"
+        )
+    }
+
+    #[test]
+    fn handle_request_answers_ping_without_device() {
+        let message = Message {
+            request: Request::Ping,
+            password: Some(String::from("secret")),
+            serial: Some(12345678),
+            transport: yubikey::Transport::Any,
+            id: None,
+            envelope: false,
+        };
+
+        assert_eq!(
+            Response::Ok { ok: true },
+            handle_request(&message, &mut |_| {}),
+            "assert ping is answered without connecting to a YubiKey"
+        )
+    }
+
+    #[test_case("little", Some(ByteOrder::LittleEndian); "parses little endian")]
+    #[test_case("big", Some(ByteOrder::BigEndian); "parses big endian")]
+    #[test_case("native", Some(ByteOrder::Native); "parses native")]
+    #[test_case("middle", None; "fails on unknown byte order")]
+    fn byte_order_parses(name: &str, expected: Option<ByteOrder>) {
+        assert_eq!(
+            expected,
+            ByteOrder::parse(name),
+            "assert byte order is parsed"
+        )
+    }
+
+    #[test_case("a0000005272101", Some(yubikey::OATH_AID.to_vec()); "parses standard aid")]
+    #[test_case(" D2760001240102 ", Some(b"\xd2\x76\x00\x01\x24\x01\x02".to_vec()); "parses uppercase aid with whitespace")]
+    #[test_case("a000000527", Some(b"\xa0\x00\x00\x05\x27".to_vec()); "parses shortest aid")]
+    #[test_case("a0000005", None; "fails on short aid")]
+    #[test_case(&"a0".repeat(17), None; "fails on long aid")]
+    #[test_case("oath", None; "fails on non hex aid")]
+    fn parse_aid_reads_hex(aid: &str, expected: Option<Vec<u8>>) {
+        assert_eq!(expected, parse_aid(aid), "assert application ID is parsed")
+    }
+
+    #[test_case(ByteOrder::LittleEndian, b"\x0B\x00\x00\x00{\"ok\":true}"; "little endian")]
+    #[test_case(ByteOrder::BigEndian, b"\x00\x00\x00\x0B{\"ok\":true}"; "big endian")]
+    fn framing_round_trips_with_explicit_byte_order(byte_order: ByteOrder, framed: &[u8]) {
+        let serialized = serialize_response(&Response::Ok { ok: true }, byte_order).unwrap();
+        assert_eq!(framed, serialized, "assert length prefix uses byte order");
+
+        assert_eq!(
+            b"{\"ok\":true}".as_slice(),
+            read_input(&mut serialized.as_slice(), byte_order)
+                .unwrap()
+                .unwrap(),
+            "assert framed message is read back"
+        )
+    }
+
+    fn no_device() -> Result<MockCard, Error> {
+        Err(Error::Yubikey(yubikey::Error::NoDevice))
+    }
+
+    #[test]
+    fn reset_refuses_without_confirmation() {
+        let message = Message {
+            request: Request::Reset { confirm: false },
+            password: None,
+            serial: None,
+            transport: yubikey::Transport::Any,
+            id: None,
+            envelope: false,
+        };
+
+        // a reset that is not refused would fail on the missing device instead
+        assert!(
+            matches!(process(&message, no_device, &mut |_| {}, &no_cache()), Response::Error { kind, .. } if kind == "confirmation_required"),
+            "assert unconfirmed reset is refused before accessing the YubiKey"
+        )
+    }
+
+    #[test]
+    fn process_answers_device_failure_as_response() {
+        let message = Message {
+            request: Request::DeviceInfo,
+            password: None,
+            serial: None,
+            transport: yubikey::Transport::Any,
+            id: None,
+            envelope: false,
+        };
+
+        assert!(
+            matches!(process(&message, no_device, &mut |_| {}, &no_cache()), Response::Error { kind, .. } if kind == "no_device"),
+            "assert failing device is answered with its error"
+        )
+    }
+
+    #[test]
+    fn reset_acknowledges_confirmed_reset() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        assert_eq!(
+            Response::Ok { ok: true },
+            reset(|| Ok(yubikey)),
+            "assert confirmed reset is acknowledged"
+        )
+    }
+
+    #[test]
+    fn read_otp_uses_given_timestamp() {
+        // RFC 6238 test vector for the SHA1 secret "12345678901234567890" at 59 seconds
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert_eq!(
+            Response::Code {
+                account: String::from("vault"),
+                code: String::from("287082"),
+                valid_for: Some(1),
+                next_code: None,
+                next_valid_for: None,
+                timestamp: None,
+                clock_skew: None,
+                warnings: Vec::new(),
+            },
+            response,
+            "assert code and validity are derived from the timestamp"
+        )
+    }
+
+    #[test]
+    fn read_otp_calculates_next_code_one_period_later() {
+        // the credentials are listed once for both codes
+        let yubikey = vault_card(&[
+            VAULT_CODES,
+            VAULT_CODE,
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                next: true,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert_eq!(
+            Response::Code {
+                account: String::from("vault"),
+                code: String::from("287082"),
+                valid_for: Some(1),
+                next_code: Some(String::from("751162")),
+                next_valid_for: Some(31),
+                timestamp: None,
+                clock_skew: None,
+                warnings: Vec::new(),
+            },
+            response,
+            "assert next code is calculated for the following window"
+        )
+    }
+
+    #[test]
+    fn read_many_otps_continues_after_unmatched_account() {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+        let accounts = vec![String::from("vault"), String::from("zombo")];
+
+        assert_eq!(
+            serde_json::json!({"codes": [
+                {"account": "vault", "code": "287082", "valid_for": 1},
+                {"kind": "no_match", "message": "No matching credential found"},
+            ]}),
+            serde_json::to_value(read_many_otps(
+                || Ok(yubikey),
+                &accounts,
+                59,
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ))
+            .unwrap(),
+            "assert unmatched account fails in its place"
+        )
+    }
+
+    #[test]
+    fn read_many_otps_skips_touch_credential() {
+        // only the code of GitHub is answered, calculating Vault would fail
+        let yubikey = MockCard::new(&[
+            b"\x72\x06\x21Vault\x72\x07\x21GitHub\x90\x00",
+            b"\x71\x05Vault\x7c\x01\x06\x71\x06GitHub\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+            VAULT_CODE,
+        ]);
+        let accounts = vec![String::from("vault"), String::from("github")];
+
+        assert_eq!(
+            serde_json::json!({"codes": [
+                {"account": "vault", "touch_required": true},
+                {"account": "github", "code": "287082", "valid_for": 1},
+            ]}),
+            serde_json::to_value(read_many_otps(
+                || Ok(yubikey),
+                &accounts,
+                59,
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ))
+            .unwrap(),
+            "assert touch credential does not hold up the following account"
+        )
+    }
+
+    #[test]
+    fn read_all_otps_marks_touch_credentials() {
+        let yubikey = MockCard::new(&[
+            b"\x71\x06GitHub\x76\x05\x06\x41\x39\x7e\xea\x71\x05Vault\x7c\x01\x06\x90\x00",
+        ]);
+
+        assert_eq!(
+            serde_json::json!({"codes": [
+                {"account": "GitHub", "code": "287082", "valid_for": 1, "touch_required": false},
+                {"account": "Vault", "code": null, "touch_required": true},
+            ]}),
+            serde_json::to_value(read_all_otps(|| Ok(yubikey), 59)).unwrap(),
+            "assert touch credentials have no code"
+        )
+    }
+
+    #[test]
+    fn read_issuer_otps_returns_only_credentials_of_issuer() {
+        let yubikey = MockCard::new(&[
+            b"\x71\x09AWS:alice\x76\x05\x06\x41\x39\x7e\xea\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x71\x07aws:bob\x7c\x01\x06\x71\x0e60/AWS:counter\x77\x01\x06\x90\x00",
+        ]);
+
+        assert_eq!(
+            serde_json::json!({"codes": [
+                {"account": "AWS:alice", "code": "287082", "valid_for": 1, "touch_required": false},
+                {"account": "aws:bob", "code": null, "touch_required": true},
+            ]}),
+            serde_json::to_value(read_issuer_otps(|| Ok(yubikey), "AWS", 59)).unwrap(),
+            "assert credentials of other issuers and HOTP names with a slash are left out"
+        )
+    }
+
+    #[test]
+    fn with_timeout_stops_waiting_for_slow_operation() {
+        let result = with_timeout(Duration::from_millis(10), || {
+            thread::sleep(Duration::from_millis(100));
+            Ok(())
+        });
+
+        assert!(
+            matches!(result, Err(Error::Timeout)),
+            "assert slow operation times out"
+        )
+    }
+
+    #[test]
+    fn read_accounts_list_times_out_on_hanging_device() {
+        let yubikey = MockCard::with_delay(&[VAULT_LIST], Duration::from_millis(100));
+
+        let response = read_accounts_list(
+            || Ok(yubikey),
+            ListOptions::default(),
+            Duration::from_millis(10),
+            no_cache().of(DeviceKey::default()),
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "timeout"),
+            "assert hanging device is reported as timeout"
+        )
+    }
+
+    #[test]
+    fn read_otp_times_out_on_hanging_device() {
+        let yubikey = MockCard::with_delay(&[VAULT_LIST], Duration::from_millis(100));
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Exact,
+            code_options(),
+            Timeouts {
+                request: Duration::from_millis(10),
+                touch: DEFAULT_TOUCH_TIMEOUT,
+                heartbeat: DEFAULT_HEARTBEAT,
+            },
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "timeout"),
+            "assert hanging device is reported as timeout"
+        )
+    }
+
+    #[test]
+    fn deserialize_request_reports_offset_of_invalid_utf8() {
+        let result = deserialize_request(b"{\"type\":\"\xff\"}");
+
+        assert!(
+            matches!(
+                result,
+                Err(Rejected {
+                    error: Error::InvalidUtf8(9),
+                    ..
+                })
+            ),
+            "assert offset of the first invalid byte is reported"
+        );
+        assert_eq!(
+            Response::Error {
+                kind: String::from("invalid_utf8"),
+                message: String::from("Request is not valid UTF-8 at byte 9")
+            },
+            error_response(&result.unwrap_err().error),
+            "assert offset is part of the error message"
+        )
+    }
+
+    #[test]
+    fn deserialize_request_names_unknown_type() {
+        let response = match deserialize_request(b"{\"type\":\"Bogus\"}") {
+            Ok(_) => panic!("unexpected request"),
+            Err(rejected) => error_response(&rejected.error),
+        };
+
+        assert!(
+            matches!(
+                response,
+                Response::Error { kind, message } if kind == "bad_request"
+                    && message.starts_with("Unknown request type Bogus, supported are AccountList, Code,")
+            ),
+            "assert unknown type is answered with the supported ones"
+        )
+    }
+
+    #[test]
+    fn request_types_are_deserialized() {
+        for request_type in REQUEST_TYPES.iter().chain(HIDDEN_REQUEST_TYPES) {
+            let error =
+                serde_json::from_value::<Request>(serde_json::json!({ "type": request_type }))
+                    .err()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default();
+
+            assert!(
+                !error.contains("unknown variant"),
+                "assert {} is a request type",
+                request_type
+            )
+        }
+    }
+
+    // serde names every variant in the error for an unknown one, so no variant can be missed
+    fn request_variants() -> Vec<String> {
+        let error = serde_json::from_value::<Request>(serde_json::json!({ "type": "" }))
+            .unwrap_err()
+            .to_string();
+        let (_, expected) = error.split_once("expected one of").unwrap();
+        expected
+            .split('`')
+            .skip(1)
+            .step_by(2)
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn every_request_type_is_listed() {
+        // every field any request requires, the others are ignored
+        let fields = serde_json::json!({
+            "account": "vault",
+            "issuer": "AWS",
+            "accounts": [],
+            "code": "287082",
+            "secret_base32": "MZXW6YTBOI",
+            "counter": 1,
+            "uris": [],
+            "data": "",
+            "from": "old",
+            "to": "new",
+            "slot": 1,
+            "challenge_hex": "",
+            "payload": "",
+            "name": "new",
+            "secret": "MZXW6YTBOI",
+            "algorithm": "sha1",
+            "digits": 6,
+            "oath_type": "totp",
+            "require_touch": true,
+        });
+        let variants = request_variants();
+        assert!(variants.len() > 1, "assert variants are named in the error");
+
+        for variant in variants {
+            let mut message = fields.clone();
+            message["type"] = serde_json::Value::from(variant.as_str());
+            let request = serde_json::from_value::<Request>(message).unwrap();
+
+            assert_eq!(
+                variant,
+                request.kind(),
+                "assert kind is the name of the type"
+            );
+            assert!(
+                REQUEST_TYPES.contains(&request.kind())
+                    || HIDDEN_REQUEST_TYPES.contains(&request.kind()),
+                "assert {} is a listed request type",
+                variant
+            )
+        }
+    }
+
+    #[test]
+    fn error_response_truncates_long_message() {
+        let response = error_response(&Error::BadJson("x".repeat(1000)));
+
+        match response {
+            Response::Error { message, .. } => {
+                assert_eq!(
+                    MAX_ERROR_MESSAGE_LENGTH,
+                    message.chars().count(),
+                    "assert message is bounded"
+                );
+                assert!(message.ends_with("x…"), "assert truncation is marked")
+            }
+            response => panic!("unexpected response {:?}", response),
+        }
+    }
+
+    #[test_case("Bogus"; "with short type")]
+    #[test_case(&"x".repeat(1000); "with long type")]
+    fn error_response_bounds_unknown_type_message(request_type: &str) {
+        let response = error_response(&Error::UnknownRequestType(request_type.to_owned()));
+
+        assert!(
+            matches!(response, Response::Error { message, .. } if message.chars().count() <= MAX_ERROR_MESSAGE_LENGTH),
+            "assert supported types count against the bound"
+        )
+    }
+
+    #[test]
+    fn error_response_strips_control_characters() {
+        assert_eq!(
+            Response::Error {
+                kind: String::from("bad_request"),
+                message: String::from("Could not read request: unexpected [2J value")
+            },
+            error_response(&Error::BadJson(String::from(
+                "unexpected \x1b[2J\n\u{7} value"
+            ))),
+            "assert non-printable characters are removed"
+        )
+    }
+
+    #[test]
+    fn deserialize_request_reports_serde_detail() {
+        let message_of =
+            |bytes: &[u8]| match error_response(&deserialize_request(bytes).unwrap_err().error) {
+                Response::Error { kind, message } => {
+                    assert_eq!("bad_request", kind, "assert detail stays out of the kind");
+                    message
+                }
+                response => panic!("unexpected response {:?}", response),
+            };
+
+        let type_mismatch = message_of(b"{\"type\":\"Code\",\"account\":22}");
+        let missing_field = message_of(b"{\"type\":\"CodeExact\"}");
+
+        assert!(
+            type_mismatch.starts_with("Could not read request: invalid type"),
+            "assert type mismatch is described, got {}",
+            type_mismatch
+        );
+        assert!(
+            missing_field.starts_with("Could not read request: missing field `account`"),
+            "assert missing field is described, got {}",
+            missing_field
+        )
+    }
+
+    #[test_case(Some("git"), vec!["github", "gitlab"]; "lists matching accounts")]
+    #[test_case(Some("zombo"), vec![]; "lists no accounts without match")]
+    #[test_case(None, vec!["github", "gitlab", "Vault"]; "lists all accounts without query")]
+    fn read_accounts_list_filters_by_query(query: Option<&str>, expected: Vec<&str>) {
+        let yubikey =
+            MockCard::new(&[b"\x72\x07\x21github\x72\x07\x21gitlab\x72\x06\x21Vault\x90\x00"]);
+
+        assert_eq!(
+            Response::AccountList {
+                accounts: expected.into_iter().map(String::from).collect(),
+                unparseable: 0
+            },
+            read_accounts_list(
+                || Ok(yubikey),
+                ListOptions {
+                    query: query.map(String::from),
+                    ..Default::default()
+                },
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ),
+            "assert accounts are filtered by query"
+        )
+    }
+
+    #[test_case(false, vec!["gitlab", "Vault", "github"]; "keeps device order")]
+    #[test_case(true, vec!["github", "gitlab", "Vault"]; "sorts case insensitively")]
+    fn read_accounts_list_orders_on_request(sort: bool, expected: Vec<&str>) {
+        let yubikey =
+            MockCard::new(&[b"\x72\x07\x21gitlab\x72\x06\x21Vault\x72\x07\x21github\x90\x00"]);
+
+        assert_eq!(
+            Response::AccountList {
+                accounts: expected.into_iter().map(String::from).collect(),
+                unparseable: 0
+            },
+            read_accounts_list(
+                || Ok(yubikey),
+                ListOptions {
+                    sort,
+                    ..Default::default()
+                },
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ),
+            "assert accounts are only sorted if requested"
+        )
+    }
+
+    #[test_case(false, vec!["Vault", "github", "vault"]; "in device order")]
+    #[test_case(true, vec!["github", "Vault", "vault"]; "when sorted")]
+    fn read_accounts_list_drops_duplicates(sort: bool, expected: Vec<&str>) {
+        let yubikey = MockCard::new(&[
+            b"\x72\x06\x21Vault\x72\x07\x21github\x72\x06\x21Vault\x72\x06\x21vault\x90\x00",
+        ]);
+
+        assert_eq!(
+            Response::AccountList {
+                accounts: expected.into_iter().map(String::from).collect(),
+                unparseable: 0
+            },
+            read_accounts_list(
+                || Ok(yubikey),
+                ListOptions {
+                    sort,
+                    ..Default::default()
+                },
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ),
+            "assert only exact duplicates are dropped"
+        )
+    }
+
+    #[test_case(oath::OathType::Totp, vec!["github", "Vault"]; "lists totp accounts")]
+    #[test_case(oath::OathType::Hotp, vec!["counter"]; "lists hotp accounts")]
+    fn read_accounts_list_filters_by_type(oath_type: oath::OathType, expected: Vec<&str>) {
+        let yubikey =
+            MockCard::new(&[b"\x72\x07\x21github\x72\x08\x11counter\x72\x06\x21Vault\x90\x00"]);
+
+        assert_eq!(
+            Response::AccountList {
+                accounts: expected.into_iter().map(String::from).collect(),
+                unparseable: 0
+            },
+            read_accounts_list(
+                || Ok(yubikey),
+                ListOptions {
+                    oath_type: Some(oath_type),
+                    ..Default::default()
+                },
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ),
+            "assert only accounts of the type are listed"
+        )
+    }
+
+    #[test]
+    fn read_accounts_list_answers_from_cache() {
+        let cache = ListingCache::new(Duration::from_secs(60));
+        let yubikey =
+            MockCard::new(&[b"\x72\x07\x21github\x72\x07\x21gitlab\x72\x06\x21Vault\x90\x00"]);
+        read_accounts_list(
+            || Ok(yubikey),
+            ListOptions::default(),
+            DEFAULT_REQUEST_TIMEOUT,
+            cache.of(DeviceKey::default()),
+        );
+
+        assert_eq!(
+            Response::AccountList {
+                accounts: vec![String::from("github"), String::from("gitlab")],
+                unparseable: 0
+            },
+            read_accounts_list(
+                || Err::<MockCard, _>(Error::Yubikey(yubikey::Error::NoDevice)),
+                ListOptions {
+                    query: Some(String::from("git")),
+                    ..Default::default()
+                },
+                DEFAULT_REQUEST_TIMEOUT,
+                cache.of(DeviceKey::default())
+            ),
+            "assert cached listing is filtered without accessing the device"
+        )
+    }
+
+    #[test_case(Request::DeleteCredential { account: String::from("Vault") }, None; "clears listing on mutation")]
+    #[test_case(Request::Ping, Some(vec![String::from("Vault")]); "keeps listing otherwise")]
+    fn invalidate_listing_clears_on_mutation(request: Request, expected: Option<Vec<String>>) {
+        let cache = ListingCache::new(Duration::from_secs(60));
+        let device = cache.of(DeviceKey::default());
+        device.store_listing(&oath::list_all_credentials(&MockCard::new(&[VAULT_LIST])).unwrap());
+        device.store_credentials(
+            &oath::list_credentials_detailed(&vault_card(&[VAULT_CODES]), 59).unwrap(),
+        );
+
+        invalidate_listing(&cache, &request);
+
+        assert_eq!(
+            (expected.clone(), expected),
+            (
+                device.listing().map(|listing| listing.names()),
+                device
+                    .credentials()
+                    .map(|creds| creds.into_iter().map(|cred| cred.id).collect())
+            ),
+            "assert only a mutation clears the cached listings"
+        )
+    }
+
+    #[test]
+    fn read_otp_matches_cached_credentials() {
+        let cache = ListingCache::new(Duration::from_secs(60));
+        let read = |yubikey: MockCard| {
+            read_otp(
+                move || Ok(yubikey),
+                "vault",
+                Matching::Fuzzy(oath::MatchOptions::default()),
+                code_options(),
+                TIMEOUTS,
+                cache.of(DeviceKey::default()),
+                &mut |_| {},
+            )
+        };
+
+        read(vault_card(&[VAULT_CODES, VAULT_CODE]));
+        // listing again would take the code for the names and find no credential
+        let response = read(MockCard::new(&[VAULT_CODE]));
+
+        assert!(
+            matches!(&response, Response::Code { code, .. } if code == "287082"),
+            "assert only the code is calculated within the TTL, got {:?}",
+            response
+        )
+    }
+
+    #[test]
+    fn resolve_matches_cached_listing() {
+        let cache = ListingCache::new(Duration::from_secs(60));
+
+        resolve(
+            || Ok(MockCard::new(&[VAULT_LIST])),
+            "vault",
+            cache.of(DeviceKey::default()),
+        );
+        let response = resolve(
+            || Err::<MockCard, _>(Error::Yubikey(yubikey::Error::NoDevice)),
+            "vault",
+            cache.of(DeviceKey::default()),
+        );
+
+        assert_eq!(
+            Response::Resolved {
+                account: String::from("Vault")
+            },
+            response,
+            "assert cached listing is matched without accessing the device"
+        )
+    }
+
+    #[test]
+    fn read_accounts_list_reports_unparseable_credentials() {
+        let yubikey =
+            MockCard::new(&[b"\x72\x07\x21github\x72\x03\x21\xff\xfe\x72\x06\x21Vault\x90\x00"]);
+
+        assert_eq!(
+            serde_json::json!({"accounts": ["github", "Vault"], "unparseable": 1}),
+            serde_json::to_value(read_accounts_list(
+                || Ok(yubikey),
+                ListOptions::default(),
+                DEFAULT_REQUEST_TIMEOUT,
+                no_cache().of(DeviceKey::default())
+            ))
+            .unwrap(),
+            "assert readable accounts are listed despite a corrupt one"
+        )
+    }
+
+    #[test_case(b"\x72\x07\x21github\x72\x06\x21Vault\x90\x00", Response::Resolved { account: String::from("github") }; "resolves unique match")]
+    #[test_case(b"\x72\x07\x21github\x72\x07\x21gitlab\x90\x00", Response::Ambiguous { account: String::from("git"), candidates: vec![String::from("github"), String::from("gitlab")] }; "reports ambiguous match")]
+    fn resolve_matches_without_calculation(list_response: &[u8], expected: Response) {
+        // only the list response is available, any calculation would fail
+        let yubikey = MockCard::new(&[list_response]);
+
+        assert_eq!(
+            expected,
+            resolve(|| Ok(yubikey), "git", no_cache().of(DeviceKey::default())),
+            "assert search term is resolved from the list of credentials"
+        )
+    }
+
+    #[test]
+    fn resolve_reads_term_like_code() {
+        let list = b"\x72\x0b\x21My Vault 1\x72\x07\x21github\x90\x00".as_slice();
+        let resolving = MockCard::new(&[list]);
+        let calculating = MockCard::new(&[
+            list,
+            b"\x71\x0aMy Vault 1\x76\x05\x06\x41\x39\x7e\xea\x71\x06github\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+            VAULT_CODE,
+        ]);
+
+        let resolved = resolve(
+            || Ok(resolving),
+            "  my   vault ",
+            no_cache().of(DeviceKey::default()),
+        );
+        let code = read_otp(
+            move || Ok(calculating),
+            "  my   vault ",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert_eq!(
+            Response::Resolved {
+                account: String::from("My Vault 1")
+            },
+            resolved,
+            "assert untrimmed term is resolved"
+        );
+        assert!(
+            matches!(code, Response::Code { .. }),
+            "assert code request for the same term succeeds, got {:?}",
+            code
+        )
+    }
+
+    #[test]
+    fn resolve_refuses_empty_term() {
+        let yubikey = MockCard::new(&[]);
+
+        assert!(
+            matches!(resolve(|| Ok(yubikey), "  ", no_cache().of(DeviceKey::default())), Response::Error { kind, .. } if kind == "bad_request"),
+            "assert blank term is a bad request"
+        )
+    }
+
+    #[test_case("github", true; "reports existing credential")]
+    #[test_case("git", false; "reports missing credential")]
+    fn credential_exists_checks_exact_name(name: &str, exists: bool) {
+        let yubikey = MockCard::new(&[b"\x72\x07\x21github\x90\x00"]);
+
+        assert_eq!(
+            Response::Exists {
+                account: name.to_owned(),
+                exists
+            },
+            credential_exists(|| Ok(yubikey), name),
+            "assert existence of the exact name is reported"
+        )
+    }
+
+    // the credential is resolved once, then every step is calculated
+    fn vault_steps(values: &[&[u8]]) -> MockCard {
+        let responses: Vec<Vec<u8>> = [VAULT_LIST.to_vec(), VAULT_CODES.to_vec()]
+            .into_iter()
+            .chain(
+                values
+                    .iter()
+                    .map(|value| [b"\x76\x05\x06".as_slice(), value, b"\x90\x00"].concat()),
+            )
+            .collect();
+        MockCard::new(&responses.iter().map(Vec::as_slice).collect::<Vec<_>>())
+    }
+
+    #[test_case(&[b"\x41\x39\x7e\xea"], "287082", 0, true; "accepts exact match")]
+    #[test_case(&[b"\x00\x00\x00\x01", b"\x41\x39\x7e\xea", b"\x00\x0b\x76\x3a"], "751162", 1, true; "accepts code within window")]
+    #[test_case(&[b"\x41\x39\x7e\xea"], "751162", 0, false; "rejects code one step off without window")]
+    #[test_case(&[b"\x00\x00\x00\x01", b"\x41\x39\x7e\xea", b"\x00\x0b\x76\x3a"], "123456", 1, false; "rejects mismatch")]
+    #[test_case(&[b"\x41\x39\x7e\xea"], "28708", 0, false; "rejects truncated code")]
+    fn verify_compares_code(values: &[&[u8]], code: &str, window: u32, valid: bool) {
+        let yubikey = vault_steps(values);
+
+        assert_eq!(
+            Response::Verify {
+                account: String::from("vault"),
+                valid
+            },
+            verify(
+                || Ok(yubikey),
+                "vault",
+                code,
+                59,
+                window,
+                TIMEOUTS,
+                &mut |_| {}
+            ),
+            "assert code is compared within the window"
+        )
+    }
+
+    #[test]
+    fn verify_rejects_window_out_of_range() {
+        let yubikey = MockCard::new(&[]);
+
+        assert!(
+            matches!(verify(|| Ok(yubikey), "vault", "287082", 59, 11, TIMEOUTS, &mut |_| {}), Response::Error { kind, message } if kind == "bad_request" && message == "Window is out of range"),
+            "assert window beyond the step offset limit is a bad request"
+        )
+    }
+
+    #[test]
+    fn verify_refuses_window_before_epoch() {
+        let yubikey = vault_steps(&[]);
+
+        assert!(
+            matches!(verify(|| Ok(yubikey), "vault", "287082", 59, 2, TIMEOUTS, &mut |_| {}), Response::Error { kind, message } if kind == "bad_request" && message == "Timestamp is out of range"),
+            "assert step before the epoch is refused instead of calculated as another step"
+        )
+    }
+
+    #[test]
+    fn verify_prompts_once_for_touch_credential() {
+        let yubikey = vault_card(&[
+            VAULT_TOUCH_CODES,
+            b"\x76\x05\x06\x00\x00\x00\x01\x90\x00",
+            VAULT_CODE,
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+        let mut notified = Vec::new();
+
+        let response = verify(
+            || Ok(yubikey),
+            "vault",
+            "287082",
+            59,
+            1,
+            TIMEOUTS,
+            &mut |r| notified.push(r.clone()),
+        );
+
+        assert_eq!(
+            Response::Verify {
+                account: String::from("vault"),
+                valid: true
+            },
+            response,
+            "assert code of the touch credential is verified"
+        );
+        assert_eq!(
+            vec![Response::TouchRequired {
+                account: String::from("vault"),
+                touch_required: true
+            }],
+            notified,
+            "assert touch is prompted for once"
+        )
+    }
+
+    #[test]
+    fn export_formats_credentials_as_uris() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x21GitHub:alice\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+
+        assert_eq!(
+            Response::Export {
+                credentials: vec![String::from(
+                    "otpauth://totp/GitHub:alice?issuer=GitHub&algorithm=SHA1&digits=6&period=30"
+                )]
+            },
+            export(|| Ok(yubikey)),
+            "assert every credential is exported as URI"
+        )
+    }
+
+    #[test]
+    fn import_migration_reports_result_per_credential() {
+        let yubikey = MockCard::new(&[b"\x90\x00", b"\x6a\x84"]);
+
+        let response = serde_json::to_value(import_migration(
+            || Ok(yubikey),
+            "otpauth-migration://offline?data=Ci4KCkhlbGxvId6tvu8SEWFsaWNlQGV4YW1wbGUuY29tGgdFeGFtcGxlIAEoATACCicKFDEyMzQ1Njc4OTAxMjM0NTY3ODkwEgdjb3VudGVyIAIoAjABOAUQARgBIAAouWA%3D",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            serde_json::json!({"results": [
+                {"account": "Example:alice@example.com", "ok": true},
+                {"account": "counter", "kind": "device_rejected", "message": "YubiKey rejected the command"},
+            ]}),
+            response,
+            "assert every credential is answered with its own result"
+        )
+    }
+
+    #[test]
+    fn import_migration_fails_on_malformed_payload() {
+        let response = import_migration(|| Ok(MockCard::new(&[])), "not base64!");
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "invalid_migration"),
+            "assert unreadable payload fails the request"
+        )
+    }
+
+    #[test]
+    fn import_reports_result_per_uri() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+        let uris = vec![
+            String::from("otpauth://totp/alice?secret=MZXW6YTBOI"),
+            String::from("otpauth://totp/bob"),
+        ];
+
+        let response = serde_json::to_value(import(|| Ok(yubikey), &uris)).unwrap();
+
+        assert_eq!(
+            serde_json::json!({"results": [
+                {"uri": "otpauth://totp/alice?secret=MZXW6YTBOI", "ok": true},
+                {"uri": "otpauth://totp/bob", "kind": "missing_secret", "message": "URI contains no secret"},
+            ]}),
+            response,
+            "assert every URI is answered with its own result"
+        )
+    }
+
+    #[test_case(false, None; "omits timestamp by default")]
+    #[test_case(true, Some(59); "includes timestamp if requested")]
+    fn read_otp_includes_timestamp_on_request(include_timestamp: bool, expected: Option<u64>) {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                include_timestamp,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Code { timestamp, .. } if timestamp == expected),
+            "assert timestamp of the calculation is only included if requested"
+        )
+    }
+
+    #[test_case(None, None; "omits clock skew without client time")]
+    #[test_case(Some(104), Some(45); "reports client clock ahead of host")]
+    #[test_case(Some(20), Some(-39); "reports client clock behind host")]
+    #[test_case(Some(1 << 63), Some(i64::MAX - 58); "reports huge client time without overflow")]
+    fn read_otp_reports_clock_skew(client_time: Option<u64>, expected: Option<i64>) {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                client_time,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Code { clock_skew, .. } if clock_skew == expected),
+            "assert skew is the client time minus the host time"
+        )
+    }
+
+    #[test_case(u64::MAX, 0, i64::MAX; "saturates client clock far ahead")]
+    #[test_case(0, u64::MAX - 5, i64::MIN; "saturates client clock far behind")]
+    #[test_case(u64::MAX, u64::MAX - 5, 5; "subtracts times near the maximum")]
+    fn clock_skew_does_not_overflow(client_time: u64, timestamp: u64, expected: i64) {
+        assert_eq!(
+            expected,
+            clock_skew(client_time, timestamp),
+            "assert skew is the difference within the range of an i64"
+        )
+    }
+
+    #[test_case("vau", vec![String::from("matched by fuzzy search")]; "warns about fuzzy match")]
+    #[test_case("VAULT", Vec::new(); "does not warn about full name")]
+    fn read_otp_warns_about_fuzzy_match(account: &str, expected: Vec<String>) {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            account,
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Code { warnings, .. } if warnings == expected),
+            "assert only a guessed credential is warned about"
+        )
+    }
+
+    #[test]
+    fn read_otp_refuses_next_code_beyond_end_of_time() {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                timestamp: u64::MAX - 1,
+                next: true,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "bad_request"),
+            "assert huge timestamp is a bad request instead of a panic"
+        )
+    }
+
+    #[test]
+    fn read_otp_refuses_step_before_epoch() {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                step_offset: -2,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "bad_request"),
+            "assert step before the epoch is a bad request instead of the first step"
+        )
+    }
+
+    #[test_case(true; "streams progress while waiting")]
+    #[test_case(false; "only prompts for touch without streaming")]
+    fn read_otp_reports_touch_progress(stream: bool) {
+        let yubikey = MockCard::with_delay(
+            &[VAULT_LIST, VAULT_TOUCH_CODES, VAULT_CODE],
+            Duration::from_millis(100),
+        );
+        let mut notifications = Vec::new();
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                stream,
+                ..code_options()
+            },
+            Timeouts {
+                heartbeat: Duration::from_millis(20),
+                ..TIMEOUTS
+            },
+            no_cache().of(DeviceKey::default()),
+            &mut |r| notifications.push(r.clone()),
+        );
+
+        assert!(
+            matches!(&notifications[0], Response::TouchRequired { .. }),
+            "assert touch prompt is sent first"
+        );
+        let waiting = notifications[1..]
+            .iter()
+            .map(|r| match r {
+                Response::TouchWaiting { elapsed_ms, .. } => *elapsed_ms,
+                r => panic!("unexpected notification {:?}", r),
+            })
+            .collect::<Vec<u64>>();
+        assert_eq!(
+            stream,
+            !waiting.is_empty(),
+            "assert progress is only sent when streaming"
+        );
+        assert!(
+            waiting.windows(2).all(|pair| pair[0] < pair[1]),
+            "assert elapsed time increases"
+        );
+        assert!(
+            matches!(response, Response::Code { code, .. } if code == "287082"),
+            "assert code follows the progress"
+        )
+    }
+
+    #[test_case(" git hub\n", Matching::Exact, "git hub"; "trims exact name")]
+    #[test_case(" my   bank\t", Matching::Fuzzy(oath::MatchOptions::default()), "my bank"; "collapses whitespace of fuzzy term")]
+    fn normalize_search_term_removes_whitespace(
+        search_term: &str,
+        matching: Matching,
+        expected: &str,
+    ) {
+        assert_eq!(
+            expected,
+            normalize_search_term(search_term, matching),
+            "assert whitespace is normalized"
+        )
+    }
+
+    #[test]
+    fn read_otp_matches_untrimmed_search_term() {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "  vault\n",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Code { account, code, .. } if account == "vault" && code == "287082"),
+            "assert surrounding whitespace is ignored"
+        )
+    }
+
+    #[test_case("", Some("vault"), "vault"; "applies default to empty account")]
+    #[test_case(" ", Some("vault"), "vault"; "applies default to blank account")]
+    #[test_case("github", Some("vault"), "github"; "keeps given account")]
+    #[test_case("", None, ""; "keeps empty account without default")]
+    fn with_default_account_resolves_account(account: &str, default: Option<&str>, expected: &str) {
+        assert_eq!(
+            expected,
+            with_default_account(account, default),
+            "assert default is only used without account"
+        )
+    }
+
+    #[test]
+    fn read_otp_uses_default_account() {
+        let yubikey = vault_card(&[VAULT_CODES, VAULT_CODE]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            with_default_account("", Some("vault")),
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Code { account, code, .. } if account == "vault" && code == "287082"),
+            "assert code of the default account is calculated"
+        )
+    }
+
+    #[test]
+    fn read_otp_rejects_empty_account_without_default() {
+        let yubikey = MockCard::new(&[]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            with_default_account("", None),
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "bad_request"),
+            "assert empty account without default is a bad request"
+        )
+    }
+
+    #[test]
+    fn read_otp_rejects_whitespace_search_term() {
+        let yubikey = MockCard::new(&[]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            " \t\n",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            code_options(),
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, .. } if kind == "bad_request"),
+            "assert empty search term is a bad request"
+        )
+    }
+
+    #[test]
+    fn read_otp_shifts_code_by_step_offset() {
+        let yubikey = vault_card(&[VAULT_CODES, b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00"]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                step_offset: 1,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Code { code, valid_for, .. } if code == "751162" && valid_for == Some(1)),
+            "assert code of the following period is returned"
+        )
+    }
+
+    #[test_case(MAX_STEP_OFFSET + 1; "rejects offset into the future")]
+    #[test_case(-MAX_STEP_OFFSET - 1; "rejects offset into the past")]
+    #[test_case(i64::MAX; "rejects largest offset")]
+    #[test_case(i64::MIN; "rejects smallest offset")]
+    fn read_otp_rejects_step_offset_out_of_range(step_offset: i64) {
+        let yubikey = MockCard::new(&[]);
+
+        let response = read_otp(
+            || Ok(yubikey),
+            "vault",
+            Matching::Fuzzy(oath::MatchOptions::default()),
+            CodeOptions {
+                step_offset,
+                ..code_options()
+            },
+            TIMEOUTS,
+            no_cache().of(DeviceKey::default()),
+            &mut |_| {},
+        );
+
+        assert!(
+            matches!(response, Response::Error { kind, message, .. } if kind == "bad_request" && message == "Step offset is out of range"),
+            "assert step offset out of range is a bad request"
         )
     }
 }
@@ -0,0 +1,86 @@
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    let symbols = input
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .trim_end_matches('=')
+        .to_uppercase();
+
+    // a trailing group of 1, 3 or 6 symbols cannot encode whole bytes
+    if symbols.is_empty() || matches!(symbols.len() % 8, 1 | 3 | 6) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(symbols.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for symbol in symbols.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == symbol)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(bytes)
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut symbols = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            symbols.push(ALPHABET[(buffer >> bits) as usize & 0x1f] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    // the remaining bits are padded with zeros to a whole symbol, without padding characters
+    if bits > 0 {
+        symbols.push(ALPHABET[(buffer << (5 - bits)) as usize & 0x1f] as char);
+    }
+
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", b"12345678901234567890"; "decodes rfc test secret")]
+    #[test_case("MZXW6YTBOI======", b"foobar"; "decodes padded input")]
+    #[test_case("mzxw 6ytb oi", b"foobar"; "ignores case and whitespace")]
+    #[test_case("MY", b"f"; "decodes partial group")]
+    fn decode_succeeds(input: &str, expected: &[u8]) {
+        assert_eq!(
+            Some(expected.to_vec()),
+            decode(input),
+            "assert decoded bytes equal expected bytes"
+        )
+    }
+
+    #[test_case(b"12345678901234567890", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"; "encodes rfc test secret")]
+    #[test_case(b"foobar", "MZXW6YTBOI"; "encodes partial group without padding")]
+    fn encode_succeeds(input: &[u8], expected: &str) {
+        assert_eq!(expected, encode(input), "assert bytes are encoded")
+    }
+
+    #[test_case(""; "fails on empty input")]
+    #[test_case("MZXW6YTB0I"; "fails on illegal symbol")]
+    #[test_case("MZX"; "fails on illegal length")]
+    fn decode_fails(input: &str) {
+        assert_eq!(None, decode(input), "assert decoding fails")
+    }
+}
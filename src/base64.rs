@@ -0,0 +1,52 @@
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    let symbols = input.trim_end_matches('=');
+
+    // a trailing group of a single symbol cannot encode a whole byte
+    if symbols.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(symbols.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for symbol in symbols.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == symbol)? as u32;
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("Zm9vYmFy", b"foobar"; "decodes full groups")]
+    #[test_case("Zm9vYg==", b"foob"; "decodes padded input")]
+    #[test_case("Zm9vYg", b"foob"; "decodes unpadded input")]
+    #[test_case("3q2+7w==", b"\xde\xad\xbe\xef"; "decodes symbols beyond alphanumerics")]
+    fn decode_succeeds(input: &str, expected: &[u8]) {
+        assert_eq!(
+            Some(expected.to_vec()),
+            decode(input),
+            "assert decoded bytes equal expected bytes"
+        )
+    }
+
+    #[test_case("Zm9v*mFy"; "fails on illegal symbol")]
+    #[test_case("Zm9vY"; "fails on illegal length")]
+    fn decode_fails(input: &str) {
+        assert_eq!(None, decode(input), "assert decoding fails")
+    }
+}
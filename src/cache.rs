@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/// Keeps the last value stored for a short time, answering only lookups for the same key.
+pub struct Cache<K, V> {
+    ttl: Duration,
+    entry: RefCell<Option<Entry<K, V>>>,
+}
+
+struct Entry<K, V> {
+    key: K,
+    stored_at: Instant,
+    value: V,
+}
+
+impl<K: PartialEq, V: Clone> Cache<K, V> {
+    // a zero TTL disables the cache
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: RefCell::new(None),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        match &*self.entry.borrow() {
+            Some(entry) if entry.key == *key && entry.stored_at.elapsed() < self.ttl => {
+                Some(entry.value.clone())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        *self.entry.borrow_mut() = Some(Entry {
+            key,
+            stored_at: Instant::now(),
+            value,
+        });
+    }
+
+    pub fn clear(&self) {
+        *self.entry.borrow_mut() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(1, Some("listed"); "answers same key")]
+    #[test_case(2, None; "ignores other key")]
+    fn get_matches_key(key: u32, expected: Option<&str>) {
+        let cache = Cache::new(Duration::from_secs(60));
+        cache.insert(1, "listed");
+
+        assert_eq!(
+            expected,
+            cache.get(&key),
+            "assert only the stored key is answered"
+        )
+    }
+
+    #[test]
+    fn get_ignores_expired_value() {
+        let cache = Cache::new(Duration::from_millis(10));
+        cache.insert(1, "listed");
+
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(None, cache.get(&1), "assert value expires after its TTL")
+    }
+
+    #[test]
+    fn insert_without_ttl_stores_nothing() {
+        let cache = Cache::new(Duration::ZERO);
+        cache.insert(1, "listed");
+
+        assert_eq!(None, cache.get(&1), "assert disabled cache stays empty")
+    }
+
+    #[test]
+    fn clear_removes_value() {
+        let cache = Cache::new(Duration::from_secs(60));
+        cache.insert(1, "listed");
+
+        cache.clear();
+
+        assert_eq!(None, cache.get(&1), "assert cleared value is gone")
+    }
+}
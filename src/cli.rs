@@ -0,0 +1,116 @@
+use serde_json::{Map, Value};
+
+use crate::api::{self, Error, Message};
+
+const JSONL_FLAG: &str = "--jsonl";
+
+// selects newline delimited messages instead of the length prefix of native messaging
+pub fn jsonl_mode(args: &[String]) -> bool {
+    args.first().is_some_and(|a| a == JSONL_FLAG)
+}
+
+// browsers start the host with the origin of the extension or the path of its manifest,
+// so only a request type as first argument selects the command line mode
+pub fn parse_args(args: &[String]) -> Option<Result<Message, Error>> {
+    let (request_type, fields) = args.split_first()?;
+    if request_type.is_empty() || !request_type.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut message = Map::new();
+    message.insert(String::from("type"), Value::from(request_type.as_str()));
+    for field in fields {
+        let (key, value) = match field.split_once('=') {
+            Some(pair) => pair,
+            None => return Some(Err(Error::Read)),
+        };
+        // numbers and booleans are taken as such, quoting keeps them a string
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+        message.insert(key.to_owned(), value);
+    }
+
+    Some(api::deserialize_message(Value::Object(message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::api::Request;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test_case(&["Ping"], Request::Ping; "reads request without fields")]
+    #[test_case(&["AccountList", "query=git"], Request::AccountList { query: Some(String::from("git")), sort: false, oath_type: None }; "reads string field")]
+    #[test_case(&["Code", "account=github", "timestamp=59", "next=true"], Request::Code { account: String::from("github"), timestamp: Some(59), case_insensitive: true, next: true, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "reads number and boolean fields")]
+    #[test_case(&["Code", "account=\"123\""], Request::Code { account: String::from("123"), timestamp: None, case_insensitive: true, next: false, include_timestamp: false, stream: false, step_offset: 0, client_time: None }; "reads quoted number as string")]
+    fn parse_args_reads_request(arguments: &[&str], request: Request) {
+        assert_eq!(
+            request,
+            parse_args(&args(arguments)).unwrap().unwrap().request,
+            "assert request is built from the arguments"
+        )
+    }
+
+    #[test]
+    fn parse_args_reads_common_fields() {
+        let message = parse_args(&args(&[
+            "Code",
+            "account=github",
+            "serial=123",
+            "password=secret",
+        ]))
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(
+            (Some(123), Some(String::from("secret"))),
+            (message.serial, message.password),
+            "assert serial and password are read"
+        )
+    }
+
+    #[test_case(&[]; "without arguments")]
+    #[test_case(&["chrome-extension://abcdefghijklmnop/"]; "with chrome origin")]
+    #[test_case(&["/usr/lib/mozilla/native-messaging-hosts/yktotp.json", "yktotp@example.com"]; "with firefox manifest")]
+    fn parse_args_keeps_native_messaging(arguments: &[&str]) {
+        assert!(
+            parse_args(&args(arguments)).is_none(),
+            "assert browser arguments do not select the command line mode"
+        )
+    }
+
+    #[test_case(&["--jsonl"], true; "with flag")]
+    #[test_case(&["chrome-extension://abcdefghijklmnop/"], false; "with chrome origin")]
+    #[test_case(&[], false; "without arguments")]
+    fn jsonl_mode_reads_flag(arguments: &[&str], expected: bool) {
+        assert_eq!(
+            expected,
+            jsonl_mode(&args(arguments)),
+            "assert only the flag selects the JSON Lines mode"
+        )
+    }
+
+    #[test]
+    fn parse_args_fails_on_field_without_value() {
+        assert!(
+            matches!(
+                parse_args(&args(&["Code", "account"])),
+                Some(Err(Error::Read))
+            ),
+            "assert invalid arguments are reported"
+        )
+    }
+
+    #[test]
+    fn parse_args_fails_on_unknown_request_type() {
+        assert!(
+            matches!(parse_args(&args(&["Unknown"])), Some(Err(Error::UnknownRequestType(request_type))) if request_type == "Unknown"),
+            "assert unknown request type is reported"
+        )
+    }
+}
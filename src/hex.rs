@@ -0,0 +1,50 @@
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    // from_str_radix would accept a sign as well
+    if !input.len().is_multiple_of(2) || !input.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("00ff10", b"\x00\xff\x10"; "decodes lowercase digits")]
+    #[test_case("A0B1", b"\xa0\xb1"; "decodes uppercase digits")]
+    #[test_case("", b""; "decodes empty input")]
+    fn decode_succeeds(input: &str, expected: &[u8]) {
+        assert_eq!(
+            Some(expected.to_vec()),
+            decode(input),
+            "assert decoded bytes equal expected bytes"
+        )
+    }
+
+    #[test_case("abc"; "fails on odd length")]
+    #[test_case("zz"; "fails on illegal digit")]
+    #[test_case("+1"; "fails on sign")]
+    #[test_case("éé"; "fails on non ascii input")]
+    fn decode_fails(input: &str) {
+        assert_eq!(None, decode(input), "assert decoding fails")
+    }
+
+    #[test]
+    fn encode_formats_lowercase_digits() {
+        assert_eq!(
+            "00ff10",
+            encode(b"\x00\xff\x10"),
+            "assert bytes are formatted as two digits each"
+        )
+    }
+}
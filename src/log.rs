@@ -0,0 +1,43 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::Write;
+
+// stdout carries the framed protocol, so all logging goes to stderr
+pub fn log(args: fmt::Arguments) {
+    log_if("YKTOTP_LOG", args)
+}
+
+// the framing of every message is only of interest when the length prefix is suspected
+pub fn log_framing(args: fmt::Arguments) {
+    log_if("YKTOTP_LOG_FRAMING", args)
+}
+
+fn log_if(variable: &str, args: fmt::Arguments) {
+    if is_enabled(env::var_os(variable).as_deref()) {
+        let _ = writeln!(std::io::stderr(), "yktotp-jsonapi: {}", args);
+    }
+}
+
+fn is_enabled(setting: Option<&OsStr>) -> bool {
+    matches!(setting, Some(value) if !value.is_empty() && value != "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(None, false; "is off without variable")]
+    #[test_case(Some(""), false; "is off for empty variable")]
+    #[test_case(Some("0"), false; "is off for zero")]
+    #[test_case(Some("1"), true; "is on for one")]
+    fn logging_is_enabled(setting: Option<&str>, enabled: bool) {
+        assert_eq!(
+            enabled,
+            is_enabled(setting.map(OsStr::new)),
+            "assert logging is only enabled by the variable"
+        )
+    }
+}
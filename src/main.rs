@@ -1,8 +1,26 @@
+use std::env;
+use std::io;
+
 mod api;
+mod base32;
+mod base64;
+mod cache;
+mod cli;
+mod hex;
+mod log;
+mod migration;
 mod oath;
+mod otpauth;
 mod time;
 mod yubikey;
 
 fn main() -> Result<(), api::Error> {
-    api::serve()
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if cli::jsonl_mode(&args) {
+        return api::serve_jsonl(io::stdin(), io::stdout());
+    }
+    match cli::parse_args(&args) {
+        Some(message) => api::serve_once(message, &mut io::stdout()),
+        None => api::serve_loop(),
+    }
 }
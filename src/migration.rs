@@ -0,0 +1,235 @@
+use crate::base32;
+use crate::base64;
+use crate::oath::{Algorithm, CredentialDefinition, OathType};
+use crate::otpauth;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Malformed,
+    UnsupportedAlgorithm,
+}
+
+/// A credential of the payload, named even if it cannot be provisioned.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub definition: Result<CredentialDefinition, Error>,
+}
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_FIXED64: u64 = 1;
+const WIRE_BYTES: u64 = 2;
+const WIRE_FIXED32: u64 = 5;
+
+const FIELD_OTP_PARAMETERS: u64 = 1;
+const FIELD_SECRET: u64 = 1;
+const FIELD_NAME: u64 = 2;
+const FIELD_ISSUER: u64 = 3;
+const FIELD_ALGORITHM: u64 = 4;
+const FIELD_DIGITS: u64 = 5;
+const FIELD_TYPE: u64 = 6;
+const FIELD_COUNTER: u64 = 7;
+
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+// takes either the whole otpauth-migration:// URI of the QR code or only its base64 data
+pub fn parse(data: &str) -> Result<Vec<Entry>, Error> {
+    let data = match data.strip_prefix("otpauth-migration://offline?") {
+        Some(query) => query
+            .split('&')
+            .find_map(|parameter| parameter.strip_prefix("data="))
+            .and_then(otpauth::decode)
+            .ok_or(Error::Malformed)?,
+        None => data.to_owned(),
+    };
+    let payload = base64::decode(data.trim()).ok_or(Error::Malformed)?;
+
+    // besides the credentials, the payload only numbers the batches of a split export
+    parse_fields(&payload)?
+        .into_iter()
+        .filter_map(|(field, value)| match (field, value) {
+            (FIELD_OTP_PARAMETERS, Value::Bytes(parameters)) => Some(parse_entry(parameters)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_entry(parameters: &[u8]) -> Result<Entry, Error> {
+    let mut secret = None;
+    let mut name = String::new();
+    let mut issuer = String::new();
+    let mut algorithm = 0;
+    let mut digits = 0;
+    let mut oath_type = 0;
+    let mut counter = 0;
+    for (field, value) in parse_fields(parameters)? {
+        match (field, value) {
+            (FIELD_SECRET, Value::Bytes(bytes)) => secret = Some(base32::encode(bytes)),
+            (FIELD_NAME, Value::Bytes(bytes)) => name = utf8(bytes)?,
+            (FIELD_ISSUER, Value::Bytes(bytes)) => issuer = utf8(bytes)?,
+            (FIELD_ALGORITHM, Value::Varint(v)) => algorithm = v,
+            (FIELD_DIGITS, Value::Varint(v)) => digits = v,
+            (FIELD_TYPE, Value::Varint(v)) => oath_type = v,
+            (FIELD_COUNTER, Value::Varint(v)) => counter = v,
+            _ => {}
+        }
+    }
+
+    // the name of the export only contains the issuer if the URI it was scanned from did
+    let prefix = format!("{}:", issuer);
+    if !issuer.is_empty() && !name.starts_with(&prefix) {
+        name = prefix + &name;
+    }
+    let definition = (|| {
+        Ok(CredentialDefinition {
+            name: name.clone(),
+            secret: secret.ok_or(Error::Malformed)?,
+            // unspecified values are the defaults of otpauth URIs
+            algorithm: match algorithm {
+                0 | 1 => Algorithm::Sha1,
+                2 => Algorithm::Sha256,
+                3 => Algorithm::Sha512,
+                _ => return Err(Error::UnsupportedAlgorithm),
+            },
+            digits: match digits {
+                0 | 1 => 6,
+                2 => 8,
+                _ => return Err(Error::Malformed),
+            },
+            oath_type: match oath_type {
+                0 | 2 => OathType::Totp,
+                1 => OathType::Hotp,
+                _ => return Err(Error::Malformed),
+            },
+            counter: u32::try_from(counter).map_err(|_| Error::Malformed)?,
+        })
+    })();
+
+    Ok(Entry { name, definition })
+}
+
+fn utf8(bytes: &[u8]) -> Result<String, Error> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::Malformed)
+}
+
+// the protobuf wire format, fields of fixed size are skipped as the payload has none we need
+fn parse_fields(mut data: &[u8]) -> Result<Vec<(u64, Value<'_>)>, Error> {
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let key = read_varint(&mut data)?;
+        let value = match key & 0x07 {
+            WIRE_VARINT => Value::Varint(read_varint(&mut data)?),
+            WIRE_BYTES => {
+                let length =
+                    usize::try_from(read_varint(&mut data)?).map_err(|_| Error::Malformed)?;
+                Value::Bytes(take(&mut data, length)?)
+            }
+            WIRE_FIXED64 => {
+                take(&mut data, 8)?;
+                continue;
+            }
+            WIRE_FIXED32 => {
+                take(&mut data, 4)?;
+                continue;
+            }
+            _ => return Err(Error::Malformed),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or(Error::Malformed)?;
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Malformed)
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8], Error> {
+    if data.len() < length {
+        return Err(Error::Malformed);
+    }
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    Ok(taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    // a TOTP credential with issuer and a HOTP credential without, exported as a single batch
+    const PAYLOAD: &str = "Ci4KCkhlbGxvId6tvu8SEWFsaWNlQGV4YW1wbGUuY29tGgdFeGFtcGxlIAEoATACCicKFDEyMzQ1Njc4OTAxMjM0NTY3ODkwEgdjb3VudGVyIAIoAjABOAUQARgBIAAouWA=";
+
+    #[test_case(PAYLOAD.to_owned(); "parses base64 data")]
+    #[test_case(format!("otpauth-migration://offline?data={}", PAYLOAD.replace('=', "%3D")); "parses migration uri")]
+    fn parse_reads_credentials(data: String) {
+        assert_eq!(
+            Ok(vec![
+                Entry {
+                    name: String::from("Example:alice@example.com"),
+                    definition: Ok(CredentialDefinition {
+                        name: String::from("Example:alice@example.com"),
+                        secret: String::from("JBSWY3DPEHPK3PXP"),
+                        algorithm: Algorithm::Sha1,
+                        digits: 6,
+                        oath_type: OathType::Totp,
+                        counter: 0,
+                    }),
+                },
+                Entry {
+                    name: String::from("counter"),
+                    definition: Ok(CredentialDefinition {
+                        name: String::from("counter"),
+                        secret: String::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+                        algorithm: Algorithm::Sha256,
+                        digits: 8,
+                        oath_type: OathType::Hotp,
+                        counter: 5,
+                    }),
+                },
+            ]),
+            parse(&data),
+            "assert every credential of the payload is read"
+        )
+    }
+
+    #[test]
+    fn parse_names_unsupported_credential() {
+        // an MD5 credential named alice
+        let entries = parse("Cg4KAQESBWFsaWNlIAQwAg==").unwrap();
+
+        assert_eq!(
+            vec![Entry {
+                name: String::from("alice"),
+                definition: Err(Error::UnsupportedAlgorithm),
+            }],
+            entries,
+            "assert credential the YubiKey cannot store fails on its own"
+        )
+    }
+
+    #[test_case("not base64!"; "fails on invalid base64")]
+    #[test_case("CgUKAQE="; "fails on truncated parameters")]
+    #[test_case("otpauth-migration://offline?version=1"; "fails on uri without data")]
+    fn parse_fails(data: &str) {
+        assert_eq!(
+            Err(Error::Malformed),
+            parse(data),
+            "assert payload is rejected"
+        )
+    }
+}
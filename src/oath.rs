@@ -1,48 +1,398 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::{Sha256, Sha512};
+
+use crate::base32;
 use crate::yubikey;
 
+const APDU_PUT: &[u8] = b"\x00\x01\x00\x00";
+const APDU_SET_CODE: &[u8] = b"\x00\x03\x00\x00";
+const APDU_DELETE: &[u8] = b"\x00\x02\x00\x00";
 const APDU_LIST: &[u8] = b"\x00\xa1\x00\x00";
 const APDU_REMAINING: &[u8] = b"\x00\xa5\x00\x00";
 const APDU_CALCULATE: &[u8] = b"\x00\xa2\x00\x01";
+const APDU_VALIDATE: &[u8] = b"\x00\xa3\x00\x00";
+const APDU_CALCULATE_ALL: &[u8] = b"\x00\xa4\x00\x01";
+const APDU_RESET: &[u8] = b"\x00\x04\xde\xad";
+const APDU_RENAME: &[u8] = b"\x00\x05\x00\x00";
+
+const TAG_NAME: u8 = 0x71;
+const TAG_KEY: u8 = 0x73;
+const TAG_CHALLENGE: u8 = 0x74;
+const TAG_RESPONSE: u8 = 0x75;
+const TAG_TRUNCATED_RESPONSE: u8 = 0x76;
+const TAG_HOTP: u8 = 0x77;
+const TAG_IMF: u8 = 0x7a;
+const TAG_TOUCH: u8 = 0x7c;
+const TAG_VERSION: u8 = 0x79;
+
+const PASSWORD_ITERATIONS: u32 = 1000;
+
+pub const DEFAULT_PERIOD: u64 = 30;
+
+const STEAM_ISSUER_PREFIX: &str = "Steam:";
+
+// keys shorter than this are zero-padded by the device anyway, see ykman
+const MIN_KEY_LENGTH: usize = 14;
+
+// the OATH application stores no longer names
+const MAX_NAME_LENGTH: usize = 64;
 
 #[derive(Debug)]
 pub enum Error {
     Yubikey,
     NoMatchingCredential,
-    TooManyMatchingCredentials,
+    TooManyMatchingCredentials(Vec<String>),
+    CredentialExists,
     AuthRequired,
+    InvalidSecret,
+    Rejected,
+    WrongPassword,
+    TouchTimeout,
+    NoTouchCredential,
+    TouchPolicyFixed,
+    TimeOutOfRange,
+    NameTooLong,
+    InvalidDigits,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OathType {
+    Totp,
+    Hotp,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CredentialDefinition {
+    pub name: String,
+    pub secret: String,
+    pub algorithm: Algorithm,
+    pub digits: u8,
+    pub oath_type: OathType,
+    /// The initial counter of a HOTP credential.
+    #[serde(default)]
+    pub counter: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchOptions {
+    pub case_insensitive: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self {
+            case_insensitive: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Code {
+    pub value: u32,
+    pub digits: u8,
+    pub steam: bool,
+    pub period: Option<u64>,
+}
+
+impl Code {
+    // the device answers with the truncated HMAC, whose last digits form the code, while
+    // Steam encodes the whole value in its own alphabet
+    fn from_truncated(value: u32, digits: u8, steam: bool, period: Option<u64>) -> Self {
+        let value = match 10u32.checked_pow(u32::from(digits)) {
+            Some(modulus) if !steam => value % modulus,
+            _ => value,
+        };
+        Self {
+            value,
+            digits,
+            steam,
+            period,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CredentialCode {
+    pub name: String,
+    pub code: Option<Code>,
+    pub touch: bool,
+    pub oath_type: OathType,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Credential {
+    // the raw name stored on the device, as required for exact operations
+    pub id: String,
+    pub issuer: Option<String>,
+    pub name: String,
+    pub period: Option<u64>,
+    pub digits: Option<u8>,
+    pub algorithm: Algorithm,
+    pub oath_type: OathType,
+    pub touch: bool,
+}
+
+// firmware 5.7 doubled the slots of the OATH application, earlier YubiKeys are not supported
+pub fn max_credentials(version: &yubikey::Version) -> Option<usize> {
+    match version {
+        yubikey::Version(5, minor, _) if *minor >= 7 => Some(64),
+        yubikey::Version(4 | 5, _, _) => Some(32),
+        _ => None,
+    }
+}
+
+/// The credentials that could be read, and how many could not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub entries: Vec<ListEntry>,
+    pub unparseable: usize,
+}
+
+/// A listed credential, whose type is unknown if the device reports a key type we do not know.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListEntry {
+    pub name: String,
+    pub oath_type: Option<OathType>,
+}
+
+impl Listing {
+    pub fn names(self) -> Vec<String> {
+        self.entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    fn filter(self, keep: impl Fn(&ListEntry) -> bool) -> Listing {
+        Listing {
+            entries: self.entries.into_iter().filter(keep).collect(),
+            ..self
+        }
+    }
 }
 
 pub fn list_credentials(yubikey: &impl yubikey::SmartCard) -> Result<Vec<String>, Error> {
-    let mut raw_creds: Vec<u8> = Vec::new();
-    let mut response = yubikey
-        .send_and_receive(APDU_LIST)
-        .map_err(|_| Error::Yubikey)?;
+    Ok(list_all_credentials(yubikey)?.names())
+}
+
+// a credential whose name cannot be read does not hide the others
+pub fn list_all_credentials(yubikey: &impl yubikey::SmartCard) -> Result<Listing, Error> {
+    let raw_creds = receive_all(yubikey, APDU_LIST)?;
+    let (creds, unparseable) = parse_list_partial(&raw_creds);
+    Ok(Listing {
+        entries: creds
+            .into_iter()
+            .map(|(key_type, name)| ListEntry {
+                name,
+                oath_type: oath_type_from_byte(key_type),
+            })
+            .collect(),
+        unparseable,
+    })
+}
+
+// applies the rules of the fuzzy code lookup, without the need for a unique match
+pub fn filter_listing(listing: Listing, search_term: &str, options: MatchOptions) -> Listing {
+    listing.filter(|entry| matches_search_term(&entry.name, search_term, options))
+}
+
+pub fn filter_listing_by_type(listing: Listing, oath_type: OathType) -> Listing {
+    listing.filter(|entry| entry.oath_type == Some(oath_type))
+}
+
+pub fn list_credentials_detailed(
+    yubikey: &impl yubikey::SmartCard,
+    time: u64,
+) -> Result<Vec<Credential>, Error> {
+    let raw_creds = receive_all(yubikey, APDU_LIST)?;
+
+    // only a calculation reveals digits and touch requirement, it does not wait for a touch
+    let raw_codes = receive_all(yubikey, &calculate_all_apdu(time))?;
+    let tlvs = parse_tlvs(&raw_codes);
+
+    let creds = parse_list(&raw_creds)
+        .into_iter()
+        .filter_map(|(key_type, name)| {
+            let oath_type = oath_type_from_byte(key_type)?;
+            let algorithm = algorithm_from_byte(key_type)?;
+            let properties = tlvs
+                .windows(2)
+                .find(|pair| pair[0] == (TAG_NAME, name.as_bytes()))
+                .map(|pair| pair[1]);
+
+            let (issuer, account) = split_name(&name, oath_type);
+            Some(Credential {
+                issuer: issuer.map(str::to_owned),
+                name: account.to_owned(),
+                period: match oath_type {
+                    OathType::Totp => Some(period(&name)),
+                    OathType::Hotp => None,
+                },
+                digits: properties.and_then(|(_, value)| value.first().copied()),
+                algorithm,
+                oath_type,
+                touch: matches!(properties, Some((TAG_TOUCH, _))),
+                id: name,
+            })
+        })
+        .collect();
+
+    Ok(creds)
+}
+
+// credentials requiring a touch are not calculated, as they could only be touched one at a time
+pub fn calculate_all(
+    yubikey: &impl yubikey::SmartCard,
+    time: u64,
+) -> Result<Vec<CredentialCode>, Error> {
+    let raw_codes = receive_all(yubikey, &calculate_all_apdu(time))?;
+
+    let mut codes = Vec::new();
+    for pair in parse_tlvs(&raw_codes).windows(2) {
+        let (name, (tag, value)) = match pair {
+            [(TAG_NAME, name), response] => (String::from_utf8_lossy(name).into_owned(), *response),
+            _ => continue,
+        };
+        let code = match (tag, value) {
+            // the device calculates all codes for the default period only
+            (TAG_TRUNCATED_RESPONSE, _) if period(&name) != DEFAULT_PERIOD => {
+                Some(calculate(yubikey, &name, time)?)
+            }
+            (TAG_TRUNCATED_RESPONSE, [digits, a, b, c, d]) => Some(Code::from_truncated(
+                u32::from_be_bytes([*a, *b, *c, *d]),
+                *digits,
+                name.starts_with(STEAM_ISSUER_PREFIX),
+                Some(DEFAULT_PERIOD),
+            )),
+            _ => None,
+        };
+        codes.push(CredentialCode {
+            touch: tag == TAG_TOUCH,
+            // the device answers for a HOTP credential without calculating it
+            oath_type: if tag == TAG_HOTP {
+                OathType::Hotp
+            } else {
+                OathType::Totp
+            },
+            name,
+            code,
+        });
+    }
+
+    Ok(codes)
+}
+
+fn calculate_all_apdu(time: u64) -> Vec<u8> {
+    let challenge = (time / DEFAULT_PERIOD).to_be_bytes();
+    [
+        APDU_CALCULATE_ALL,
+        &[challenge.len() as u8 + 2],
+        &[TAG_CHALLENGE],
+        &[challenge.len() as u8],
+        &challenge,
+    ]
+    .concat()
+}
+
+// every response ends with a status word, a shorter one is from a faulty device or reader
+fn receive_all(yubikey: &impl yubikey::SmartCard, apdu: &[u8]) -> Result<Vec<u8>, Error> {
+    let receive = |apdu| match yubikey.send_and_receive(apdu) {
+        Ok(response) if response.len() >= 2 => Ok(response),
+        _ => Err(Error::Yubikey),
+    };
+    let mut raw_data: Vec<u8> = Vec::new();
+    let mut response = receive(apdu)?;
 
     while response[response.len() - 2] == 0x61 {
-        raw_creds.extend(response.iter().take(response.len() - 2));
-        response = yubikey
-            .send_and_receive(APDU_REMAINING)
-            .map_err(|_| Error::Yubikey)?;
+        raw_data.extend(response.iter().take(response.len() - 2));
+        response = receive(APDU_REMAINING)?;
+    }
+
+    if response.ends_with(b"\x69\x82") {
+        return Err(Error::AuthRequired);
+    }
+
+    raw_data.append(&mut response);
+
+    Ok(raw_data)
+}
+
+pub fn calculate(yubikey: &impl yubikey::SmartCard, cred: &str, time: u64) -> Result<Code, Error> {
+    let period = period(cred);
+    let challenge = time / period;
+    let (value, digits) = send_calculate(yubikey, cred, &challenge.to_be_bytes())?;
+    Ok(Code::from_truncated(
+        value,
+        digits,
+        cred.starts_with(STEAM_ISSUER_PREFIX),
+        Some(period),
+    ))
+}
+
+// follows the key uri format: an optional period prefix for TOTP, then `issuer:account`
+pub fn split_name(cred: &str, oath_type: OathType) -> (Option<&str>, &str) {
+    let name = match oath_type {
+        OathType::Totp => split_period(cred).1,
+        OathType::Hotp => cred,
+    };
+    match name.split_once(':') {
+        Some((issuer, account)) if !issuer.is_empty() => (Some(issuer), account),
+        _ => (None, name),
+    }
+}
+
+// credentials with a period other than the default are stored with a prefix like "60/", which
+// is split off the name
+pub fn split_period(cred: &str) -> (Option<u64>, &str) {
+    match cred.split_once('/') {
+        Some((prefix, name)) => match prefix.parse() {
+            Ok(period) => (Some(period), name),
+            Err(_) => (None, cred),
+        },
+        None => (None, cred),
     }
+}
 
-    raw_creds.append(&mut response);
+fn period(cred: &str) -> u64 {
+    split_period(cred)
+        .0
+        .filter(|period| *period > 0)
+        .unwrap_or(DEFAULT_PERIOD)
+}
 
-    Ok(parse_credentials(&raw_creds))
+pub fn calculate_hotp(yubikey: &impl yubikey::SmartCard, search_term: &str) -> Result<Code, Error> {
+    // a TOTP credential would be calculated for an empty challenge, its code meaningless
+    let creds = filter_listing_by_type(list_all_credentials(yubikey)?, OathType::Hotp).names();
+    let cred = find_match(&creds, search_term, MatchOptions::default())?;
+    // HOTP credentials ignore the challenge, the device advances its own counter instead
+    let (value, digits) = send_calculate(yubikey, cred, &[])?;
+    Ok(Code::from_truncated(value, digits, false, None))
 }
 
-pub fn calculate(yubikey: &impl yubikey::SmartCard, cred: &str, time: u64) -> Result<u32, Error> {
+// returns the truncated response together with the number of digits
+fn send_calculate(
+    yubikey: &impl yubikey::SmartCard,
+    cred: &str,
+    challenge: &[u8],
+) -> Result<(u32, u8), Error> {
     let cred_bytes = cred.as_bytes();
-    let challenge = time / 30;
-    let challenge_bytes = challenge.to_be_bytes();
     let apdu = [
         APDU_CALCULATE,
-        &[cred_bytes.len() as u8 + 12],
+        &[(cred_bytes.len() + challenge.len() + 4) as u8],
         &[0x71],
         &[cred_bytes.len() as u8],
         cred_bytes,
         &[0x74],
-        &[8],
-        &challenge_bytes,
+        &[challenge.len() as u8],
+        challenge,
     ]
     .concat();
 
@@ -55,64 +405,1782 @@ pub fn calculate(yubikey: &impl yubikey::SmartCard, cred: &str, time: u64) -> Re
         return Err(Error::Yubikey);
     }
 
-    if rapdu.starts_with(b"\x6982") {
+    if rapdu.ends_with(b"\x69\x82") {
         return Err(Error::AuthRequired);
     }
 
-    if rapdu.starts_with(b"\x6984") {
+    if rapdu.ends_with(b"\x69\x84") {
         return Err(Error::NoMatchingCredential);
     }
 
-    if !rapdu.starts_with(b"\x76") {
+    if rapdu.ends_with(b"\x69\x85") {
+        return Err(Error::TouchTimeout);
+    }
+
+    if rapdu[0] != TAG_TRUNCATED_RESPONSE {
         return Err(Error::Yubikey);
     }
 
     if rapdu_len < 7 {
         Err(Error::Yubikey)
     } else {
-        Ok(u32::from_be_bytes([rapdu[3], rapdu[4], rapdu[5], rapdu[6]]))
+        Ok((
+            u32::from_be_bytes([rapdu[3], rapdu[4], rapdu[5], rapdu[6]]),
+            rapdu[2],
+        ))
     }
 }
 
+// only matches the listed names, so neither a code is calculated nor a touch required
+pub fn resolve_fuzzy(
+    names: &[String],
+    search_term: &str,
+    options: MatchOptions,
+) -> Result<String, Error> {
+    find_match(names, search_term, options).cloned()
+}
+
+// the credentials are matched as listed by `list_credentials_detailed`, only the code is
+// calculated on the device
 pub fn calculate_fuzzy(
     yubikey: &impl yubikey::SmartCard,
+    creds: &[Credential],
     search_term: &str,
+    options: MatchOptions,
     time: u64,
-) -> Result<u32, Error> {
-    let search_term_lower = search_term.to_lowercase();
+    step_offset: i64,
+    on_touch: impl FnOnce(),
+) -> Result<(String, Code), Error> {
+    calculate_matching(yubikey, creds, time, step_offset, on_touch, |names| {
+        find_match(names, search_term, options).cloned()
+    })
+}
 
-    let creds = list_credentials(yubikey).map_err(|_| Error::Yubikey)?;
+pub fn calculate_exact(
+    yubikey: &impl yubikey::SmartCard,
+    creds: &[Credential],
+    name: &str,
+    time: u64,
+    step_offset: i64,
+    on_touch: impl FnOnce(),
+) -> Result<(String, Code), Error> {
+    calculate_matching(yubikey, creds, time, step_offset, on_touch, |names| {
+        find_exact(names, name).cloned()
+    })
+}
 
-    let matching_creds = creds
+// the step offset shifts the time by whole periods of the matching credential, whose name is
+// returned along with the code
+fn calculate_matching(
+    yubikey: &impl yubikey::SmartCard,
+    creds: &[Credential],
+    time: u64,
+    step_offset: i64,
+    on_touch: impl FnOnce(),
+    find: impl FnOnce(&[String]) -> Result<String, Error>,
+) -> Result<(String, Code), Error> {
+    let (cred, touch) = find_detailed(creds, find)?;
+    let time = shift_time(time, step_offset, period(&cred))?;
+
+    // the device blocks the calculation until it is touched
+    if touch {
+        on_touch();
+    }
+
+    calculate(yubikey, &cred, time).map(|code| (cred, code))
+}
+
+// the credential is resolved once for every step of the window, the codes are in the order of the steps;
+// a credential requiring a touch is touched for every step, the signal is only given once
+pub fn calculate_window(
+    yubikey: &impl yubikey::SmartCard,
+    search_term: &str,
+    options: MatchOptions,
+    time: u64,
+    window: u32,
+    on_touch: impl FnOnce(),
+) -> Result<(String, Vec<Code>), Error> {
+    let creds = list_credentials_detailed(yubikey, time)?;
+    let (cred, touch) = find_detailed(&creds, |names| {
+        find_match(names, search_term, options).cloned()
+    })?;
+    let window = i64::from(window);
+    let times = (-window..=window)
+        .map(|step_offset| shift_time(time, step_offset, period(&cred)))
+        .collect::<Result<Vec<u64>, Error>>()?;
+
+    if touch {
+        on_touch();
+    }
+
+    let codes = times
+        .into_iter()
+        .map(|time| calculate(yubikey, &cred, time))
+        .collect::<Result<Vec<Code>, Error>>()?;
+    Ok((cred, codes))
+}
+
+// matches credentials listed before, so several search terms are resolved with one listing
+pub fn find_listed<'a>(
+    creds: &'a [Credential],
+    search_term: &str,
+    options: MatchOptions,
+) -> Result<&'a Credential, Error> {
+    let names = creds
         .iter()
-        .filter(|cred| cred.to_lowercase().contains(&search_term_lower))
-        .collect::<Vec<&String>>();
+        .map(|cred| cred.id.clone())
+        .collect::<Vec<String>>();
+    let name = find_match(&names, search_term, options)?;
+    creds
+        .iter()
+        .find(|cred| cred.id == *name)
+        .ok_or(Error::NoMatchingCredential)
+}
 
-    match matching_creds.len() {
-        0 => Err(Error::NoMatchingCredential),
-        1 => calculate(yubikey, matching_creds[0], time),
-        _ => Err(Error::TooManyMatchingCredentials),
+// returns the name of the credential found along with whether it requires a touch
+fn find_detailed(
+    creds: &[Credential],
+    find: impl FnOnce(&[String]) -> Result<String, Error>,
+) -> Result<(String, bool), Error> {
+    let names = creds
+        .iter()
+        .map(|cred| cred.id.clone())
+        .collect::<Vec<String>>();
+    let cred = find(&names)?;
+    let touch = creds.iter().any(|c| c.id == cred && c.touch);
+    Ok((cred, touch))
+}
+
+// a step before the epoch is refused like one beyond the range of time, rather than calculating
+// another step in its place
+fn shift_time(time: u64, step_offset: i64, period: u64) -> Result<u64, Error> {
+    let shift = step_offset
+        .unsigned_abs()
+        .checked_mul(period)
+        .ok_or(Error::TimeOutOfRange)?;
+    if step_offset < 0 {
+        time.checked_sub(shift).ok_or(Error::TimeOutOfRange)
+    } else {
+        time.checked_add(shift).ok_or(Error::TimeOutOfRange)
     }
 }
 
-fn parse_credentials(rapdu: &[u8]) -> Vec<String> {
-    let mut creds: Vec<String> = Vec::new();
-    let mut buf_it = rapdu.iter();
+// the OATH application reports its own version on selection, which may differ from the firmware
+pub fn applet_version(select_response: &[u8]) -> Option<yubikey::Version> {
+    match find_tag(select_response, TAG_VERSION)? {
+        [major, minor, patch] => Some(yubikey::Version(*major, *minor, *patch)),
+        _ => None,
+    }
+}
 
-    while let Some(0x72) = buf_it.next() {
-        let len = match buf_it.next() {
-            Some(len) => (*len - 1) as usize,
-            _ => break,
-        };
-        buf_it.next();
-        let cred_it = buf_it.by_ref().take(len);
-        let cred_bytes = cred_it.cloned().collect::<Vec<u8>>();
-        let cred = match std::str::from_utf8(&cred_bytes) {
-            Ok(cred) => cred,
-            Err(_) => break,
+// a password protected application answers the selection with a challenge to unlock it
+pub fn is_locked(select_response: &[u8]) -> bool {
+    find_tag(select_response, TAG_CHALLENGE).is_some()
+}
+
+// any credential requiring a touch proves the presence of the user, its code is discarded
+pub fn require_touch(
+    yubikey: &impl yubikey::SmartCard,
+    time: u64,
+    on_touch: impl FnOnce(),
+) -> Result<(), Error> {
+    let creds = list_credentials_detailed(yubikey, time)?;
+    let cred = creds
+        .into_iter()
+        .find(|cred| cred.touch)
+        .ok_or(Error::NoTouchCredential)?;
+
+    on_touch();
+    calculate(yubikey, &cred.id, time).map(|_| ())
+}
+
+pub fn unlock(
+    yubikey: &impl yubikey::SmartCard,
+    select_response: &[u8],
+    password: &str,
+) -> Result<(), Error> {
+    let challenge = match find_tag(select_response, TAG_CHALLENGE) {
+        Some(challenge) => challenge,
+        // the application is not password protected
+        None => return Ok(()),
+    };
+    let salt = find_tag(select_response, TAG_NAME).ok_or(Error::Yubikey)?;
+    let key = derive_key(password, salt);
+
+    let mut own_challenge = [0; 8];
+    getrandom::getrandom(&mut own_challenge).map_err(|_| Error::Yubikey)?;
+
+    let response = hmac_sha1(&key, challenge);
+    let data = [
+        &[TAG_RESPONSE],
+        &[response.len() as u8],
+        response.as_slice(),
+        &[TAG_CHALLENGE],
+        &[own_challenge.len() as u8],
+        &own_challenge,
+    ]
+    .concat();
+    let apdu = [APDU_VALIDATE, &[data.len() as u8], &data].concat();
+
+    let rapdu = yubikey
+        .send_and_receive(&apdu)
+        .map_err(|_| Error::Yubikey)?;
+
+    if !rapdu.ends_with(b"\x90\x00") {
+        return Err(Error::WrongPassword);
+    }
+
+    // the device has to prove that it knows the key as well
+    match find_tag(&rapdu, TAG_RESPONSE) {
+        Some(device_response) if device_response == hmac_sha1(&key, &own_challenge) => Ok(()),
+        _ => Err(Error::Yubikey),
+    }
+}
+
+// a protected application has to be unlocked with the current password first
+pub fn set_password(
+    yubikey: &impl yubikey::SmartCard,
+    select_response: &[u8],
+    password: Option<&str>,
+) -> Result<(), Error> {
+    let data = match password {
+        Some(password) => {
+            let salt = find_tag(select_response, TAG_NAME).ok_or(Error::Yubikey)?;
+            let key = derive_key(password, salt);
+
+            // the device verifies the key by answering a challenge with it
+            let mut challenge = [0; 8];
+            getrandom::getrandom(&mut challenge).map_err(|_| Error::Yubikey)?;
+            let response = hmac_sha1(&key, &challenge);
+            [
+                &[TAG_KEY],
+                &[key.len() as u8 + 1],
+                &[oath_type_byte(OathType::Totp) | algorithm_byte(Algorithm::Sha1)],
+                key.as_slice(),
+                &[TAG_CHALLENGE],
+                &[challenge.len() as u8],
+                &challenge,
+                &[TAG_RESPONSE],
+                &[response.len() as u8],
+                &response,
+            ]
+            .concat()
+        }
+        // an empty key removes the password
+        None => vec![TAG_KEY, 0],
+    };
+    let apdu = [APDU_SET_CODE, &[data.len() as u8], &data].concat();
+
+    let rapdu = yubikey
+        .send_and_receive(&apdu)
+        .map_err(|_| Error::Yubikey)?;
+
+    if rapdu.ends_with(b"\x90\x00") {
+        Ok(())
+    } else if rapdu.ends_with(b"\x69\x82") {
+        Err(Error::AuthRequired)
+    } else {
+        Err(Error::Rejected)
+    }
+}
+
+fn derive_key(password: &str, salt: &[u8]) -> [u8; 16] {
+    let mut key = [0; 16];
+    pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), salt, PASSWORD_ITERATIONS, &mut key);
+    key
+}
+
+// RFC 6238 in software, for a secret that is not stored on the device
+pub fn calculate_totp(
+    secret: &[u8],
+    algorithm: Algorithm,
+    digits: u8,
+    period: u64,
+    time: u64,
+) -> Code {
+    let value = truncate(&hmac(algorithm, secret, &(time / period).to_be_bytes()));
+    Code::from_truncated(value, digits, false, Some(period))
+}
+
+// RFC 4226 in software, so no counter is advanced
+pub fn calculate_hotp_at(secret: &[u8], algorithm: Algorithm, digits: u8, counter: u64) -> Code {
+    let value = truncate(&hmac(algorithm, secret, &counter.to_be_bytes()));
+    Code::from_truncated(value, digits, false, None)
+}
+
+// the last nibble selects the four bytes the code is taken from
+fn truncate(hash: &[u8]) -> u32 {
+    let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+    u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff
+}
+
+fn hmac(algorithm: Algorithm, key: &[u8], message: &[u8]) -> Vec<u8> {
+    match algorithm {
+        Algorithm::Sha1 => hmac_sha1(key, message),
+        Algorithm::Sha256 => {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        Algorithm::Sha512 => {
+            let mut mac =
+                Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn find_tag(data: &[u8], tag: u8) -> Option<&[u8]> {
+    parse_tlvs(data)
+        .into_iter()
+        .find(|(t, _)| *t == tag)
+        .map(|(_, value)| value)
+}
+
+fn parse_tlvs(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut tlvs = Vec::new();
+    let mut position = 0;
+    while position + 1 < data.len() {
+        let len = data[position + 1] as usize;
+        let value = match data.get(position + 2..position + 2 + len) {
+            Some(value) => value,
+            None => break,
         };
-        creds.push(cred.to_owned());
+        tlvs.push((data[position], value));
+        position += 2 + len;
     }
+    tlvs
+}
 
-    creds
+pub fn add_credential(
+    yubikey: &impl yubikey::SmartCard,
+    definition: &CredentialDefinition,
+) -> Result<(), Error> {
+    let name_bytes = check_name(&definition.name)?;
+    if !(6..=8).contains(&definition.digits) {
+        return Err(Error::InvalidDigits);
+    }
+    let mut key = shorten_key(
+        base32::decode(&definition.secret).ok_or(Error::InvalidSecret)?,
+        definition.algorithm,
+    );
+    if key.len() < MIN_KEY_LENGTH {
+        key.resize(MIN_KEY_LENGTH, 0);
+    }
+
+    let key_type = oath_type_byte(definition.oath_type) | algorithm_byte(definition.algorithm);
+    let mut data = [
+        &[0x71],
+        &[length_byte(name_bytes.len())?],
+        name_bytes,
+        &[0x73],
+        &[length_byte(key.len() + 2)?],
+        &[key_type],
+        &[definition.digits],
+        &key,
+    ]
+    .concat();
+    // the device starts counting at zero unless told otherwise
+    if definition.oath_type == OathType::Hotp && definition.counter > 0 {
+        data.extend([TAG_IMF, 4]);
+        data.extend(definition.counter.to_be_bytes());
+    }
+    let apdu = [APDU_PUT, &[length_byte(data.len())?], &data].concat();
+
+    let rapdu = yubikey
+        .send_and_receive(&apdu)
+        .map_err(|_| Error::Yubikey)?;
+
+    if rapdu.ends_with(b"\x90\x00") {
+        Ok(())
+    } else {
+        Err(Error::Rejected)
+    }
+}
+
+fn check_name(name: &str) -> Result<&[u8], Error> {
+    let name_bytes = name.as_bytes();
+    if name_bytes.len() > MAX_NAME_LENGTH {
+        return Err(Error::NameTooLong);
+    }
+    Ok(name_bytes)
+}
+
+// with the name checked and the key shortened to a block, no field exceeds a length byte
+fn length_byte(length: usize) -> Result<u8, Error> {
+    u8::try_from(length).map_err(|_| Error::NameTooLong)
+}
+
+// HMAC hashes keys longer than the block size of the algorithm, the device expects them hashed already
+fn shorten_key(key: Vec<u8>, algorithm: Algorithm) -> Vec<u8> {
+    let block_size = match algorithm {
+        Algorithm::Sha1 | Algorithm::Sha256 => 64,
+        Algorithm::Sha512 => 128,
+    };
+    if key.len() <= block_size {
+        return key;
+    }
+    match algorithm {
+        Algorithm::Sha1 => Sha1::digest(&key).to_vec(),
+        Algorithm::Sha256 => Sha256::digest(&key).to_vec(),
+        Algorithm::Sha512 => Sha512::digest(&key).to_vec(),
+    }
+}
+
+pub fn delete_credential(yubikey: &impl yubikey::SmartCard, name: &str) -> Result<(), Error> {
+    let creds = list_credentials(yubikey)?;
+    let cred = find_exact(&creds, name)?;
+
+    let cred_bytes = cred.as_bytes();
+    let apdu = [
+        APDU_DELETE,
+        &[cred_bytes.len() as u8 + 2],
+        &[0x71],
+        &[cred_bytes.len() as u8],
+        cred_bytes,
+    ]
+    .concat();
+
+    let rapdu = yubikey
+        .send_and_receive(&apdu)
+        .map_err(|_| Error::Yubikey)?;
+
+    if rapdu.ends_with(b"\x90\x00") {
+        Ok(())
+    } else {
+        Err(Error::Rejected)
+    }
+}
+
+// the touch policy is only set when a credential is put with its secret, no version of the OATH
+// application so far changes it in place
+fn changes_touch_in_place(_version: &yubikey::Version) -> bool {
+    false
+}
+
+// decided on the answer to selecting the application, so nothing is sent to a YubiKey
+// that cannot change the touch policy
+pub fn check_touch_update(select_response: &[u8]) -> Result<(), Error> {
+    match applet_version(select_response) {
+        Some(version) if changes_touch_in_place(&version) => Ok(()),
+        _ => Err(Error::TouchPolicyFixed),
+    }
+}
+
+pub fn credential_info(
+    yubikey: &impl yubikey::SmartCard,
+    name: &str,
+    time: u64,
+) -> Result<Credential, Error> {
+    list_credentials_detailed(yubikey, time)?
+        .into_iter()
+        .find(|cred| cred.id == name)
+        .ok_or(Error::NoMatchingCredential)
+}
+
+pub fn credential_exists(yubikey: &impl yubikey::SmartCard, name: &str) -> Result<bool, Error> {
+    let creds = list_credentials(yubikey)?;
+    Ok(creds.iter().any(|c| c == name))
+}
+
+pub fn rename_credential(
+    yubikey: &impl yubikey::SmartCard,
+    from: &str,
+    to: &str,
+) -> Result<(), Error> {
+    let to_bytes = check_name(to)?;
+    let creds = list_credentials(yubikey)?;
+    let cred = find_exact(&creds, from)?;
+    if creds.iter().any(|c| c == to) {
+        return Err(Error::CredentialExists);
+    }
+
+    let from_bytes = cred.as_bytes();
+    let apdu = [
+        APDU_RENAME,
+        &[length_byte(from_bytes.len() + to_bytes.len() + 4)?],
+        &[TAG_NAME],
+        &[length_byte(from_bytes.len())?],
+        from_bytes,
+        &[TAG_NAME],
+        &[length_byte(to_bytes.len())?],
+        to_bytes,
+    ]
+    .concat();
+
+    let rapdu = yubikey
+        .send_and_receive(&apdu)
+        .map_err(|_| Error::Yubikey)?;
+
+    if rapdu.ends_with(b"\x90\x00") {
+        Ok(())
+    } else {
+        Err(Error::Rejected)
+    }
+}
+
+// removes all credentials and the password, there is no way to undo this
+pub fn reset(yubikey: &impl yubikey::SmartCard) -> Result<(), Error> {
+    let rapdu = yubikey
+        .send_and_receive(APDU_RESET)
+        .map_err(|_| Error::Yubikey)?;
+
+    if rapdu.ends_with(b"\x90\x00") {
+        Ok(())
+    } else {
+        Err(Error::Rejected)
+    }
+}
+
+fn oath_type_byte(oath_type: OathType) -> u8 {
+    match oath_type {
+        OathType::Hotp => 0x10,
+        OathType::Totp => 0x20,
+    }
+}
+
+fn algorithm_byte(algorithm: Algorithm) -> u8 {
+    match algorithm {
+        Algorithm::Sha1 => 0x01,
+        Algorithm::Sha256 => 0x02,
+        Algorithm::Sha512 => 0x03,
+    }
+}
+
+fn oath_type_from_byte(key_type: u8) -> Option<OathType> {
+    match key_type & 0xf0 {
+        0x10 => Some(OathType::Hotp),
+        0x20 => Some(OathType::Totp),
+        _ => None,
+    }
+}
+
+fn algorithm_from_byte(key_type: u8) -> Option<Algorithm> {
+    match key_type & 0x0f {
+        0x01 => Some(Algorithm::Sha1),
+        0x02 => Some(Algorithm::Sha256),
+        0x03 => Some(Algorithm::Sha512),
+        _ => None,
+    }
+}
+
+// ordered from the weakest to the strongest match
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    Subsequence,
+    Substring,
+    Prefix,
+}
+
+// a search term naming the credential in full is no guess, even though it was searched for
+pub fn is_exact_match(cred: &str, search_term: &str, options: MatchOptions) -> bool {
+    let cred = split_period(cred).1;
+    if options.case_insensitive {
+        cred.to_lowercase() == search_term.to_lowercase()
+    } else {
+        cred == search_term
+    }
+}
+
+// the period prefix is no part of the name the user knows the credential by, while issuer and
+// account are matched on their own as well, so the start of either counts as a prefix
+pub fn rank(cred: &str, search_term: &str, options: MatchOptions) -> Option<Rank> {
+    let name = split_period(cred).1;
+    let parts = match name.split_once(':') {
+        Some((issuer, account)) => vec![name, issuer, account],
+        None => vec![name],
+    };
+    parts
+        .into_iter()
+        .map(|part| rank_part(part, search_term, options))
+        .max()
+        .flatten()
+}
+
+fn rank_part(cred: &str, search_term: &str, options: MatchOptions) -> Option<Rank> {
+    let (cred, search_term) = if options.case_insensitive {
+        (cred.to_lowercase(), search_term.to_lowercase())
+    } else {
+        (cred.to_owned(), search_term.to_owned())
+    };
+
+    if cred.starts_with(&search_term) {
+        Some(Rank::Prefix)
+    } else if cred.contains(&search_term) {
+        Some(Rank::Substring)
+    } else {
+        let mut chars = cred.chars();
+        search_term
+            .chars()
+            .all(|c| chars.any(|other| other == c))
+            .then_some(Rank::Subsequence)
+    }
+}
+
+fn matches_search_term(cred: &str, search_term: &str, options: MatchOptions) -> bool {
+    rank(cred, search_term, options).is_some()
+}
+
+// only the best ranked credentials are candidates, several of them are ambiguous
+fn find_match<'a>(
+    creds: &'a [String],
+    search_term: &str,
+    options: MatchOptions,
+) -> Result<&'a String, Error> {
+    let ranked_creds = creds
+        .iter()
+        .filter_map(|cred| rank(cred, search_term, options).map(|rank| (rank, cred)))
+        .collect::<Vec<(Rank, &String)>>();
+    let best_rank = ranked_creds.iter().map(|(rank, _)| *rank).max();
+    let matching_creds = ranked_creds
+        .into_iter()
+        .filter(|(rank, _)| Some(*rank) == best_rank)
+        .map(|(_, cred)| cred)
+        .collect::<Vec<&String>>();
+
+    match matching_creds.len() {
+        0 => Err(Error::NoMatchingCredential),
+        1 => Ok(matching_creds[0]),
+        _ => Err(Error::TooManyMatchingCredentials(
+            matching_creds.into_iter().cloned().collect(),
+        )),
+    }
+}
+
+fn find_exact<'a>(creds: &'a [String], name: &str) -> Result<&'a String, Error> {
+    let matching_creds = creds
+        .iter()
+        .filter(|cred| cred.as_str() == name)
+        .collect::<Vec<&String>>();
+
+    match matching_creds.len() {
+        0 => Err(Error::NoMatchingCredential),
+        1 => Ok(matching_creds[0]),
+        _ => Err(Error::TooManyMatchingCredentials(
+            matching_creds.into_iter().cloned().collect(),
+        )),
+    }
+}
+
+fn parse_list(rapdu: &[u8]) -> Vec<(u8, String)> {
+    parse_list_partial(rapdu).0
+}
+
+// the length of every entry is known, so an unreadable name is skipped and counted
+fn parse_list_partial(rapdu: &[u8]) -> (Vec<(u8, String)>, usize) {
+    let mut creds: Vec<(u8, String)> = Vec::new();
+    let mut unparseable = 0;
+    let mut buf_it = rapdu.iter();
+
+    while let Some(0x72) = buf_it.next() {
+        let len = match buf_it.next() {
+            // an entry without even a key type
+            Some(0) => {
+                unparseable += 1;
+                continue;
+            }
+            Some(len) => (*len - 1) as usize,
+            _ => break,
+        };
+        let key_type = match buf_it.next() {
+            Some(key_type) => *key_type,
+            _ => break,
+        };
+        let cred_it = buf_it.by_ref().take(len);
+        let cred_bytes = cred_it.cloned().collect::<Vec<u8>>();
+        match String::from_utf8(cred_bytes) {
+            Ok(cred) => creds.push((key_type, cred)),
+            Err(_) => unparseable += 1,
+        }
+    }
+
+    (creds, unparseable)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use crate::yubikey::mock::MockCard;
+
+    use super::*;
+
+    const LIST_RESPONSE: &[u8] = b"\x72\x08\x11counter\x72\x05\x21time\x90\x00";
+
+    #[test]
+    fn calculate_hotp_sends_empty_challenge() {
+        let yubikey = MockCard::new(&[LIST_RESPONSE, b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00"]);
+
+        let code = calculate_hotp(&yubikey, "count").unwrap();
+
+        assert_eq!(
+            Code {
+                value: 751_162,
+                digits: 6,
+                steam: false,
+                period: None
+            },
+            code,
+            "assert code is read from the response"
+        );
+        assert_eq!(
+            b"\x00\xa2\x00\x01\x0b\x71\x07counter\x74\x00".as_slice(),
+            yubikey.sent()[1],
+            "assert calculation is requested without challenge"
+        )
+    }
+
+    #[test]
+    fn calculate_hotp_ignores_totp_credentials() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x09\x21git-totp\x72\x09\x11git-hotp\x90\x00",
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+
+        calculate_hotp(&yubikey, "git").unwrap();
+
+        assert_eq!(
+            b"\x00\xa2\x00\x01\x0c\x71\x08git-hotp\x74\x00".as_slice(),
+            yubikey.sent()[1],
+            "assert only the HOTP credential is calculated"
+        )
+    }
+
+    #[test]
+    fn calculate_hotp_fails_on_totp_credential_only() {
+        let yubikey = MockCard::new(&[LIST_RESPONSE]);
+
+        assert!(
+            matches!(
+                calculate_hotp(&yubikey, "time"),
+                Err(Error::NoMatchingCredential)
+            ),
+            "assert TOTP credential is no match"
+        );
+        assert_eq!(1, yubikey.sent().len(), "assert nothing is calculated")
+    }
+
+    fn definition(secret: &str) -> CredentialDefinition {
+        CredentialDefinition {
+            name: String::from("new"),
+            secret: String::from(secret),
+            algorithm: Algorithm::Sha256,
+            digits: 8,
+            oath_type: OathType::Totp,
+            counter: 0,
+        }
+    }
+
+    #[test]
+    fn add_credential_sends_initial_counter() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        add_credential(
+            &yubikey,
+            &CredentialDefinition {
+                oath_type: OathType::Hotp,
+                counter: 0x0102,
+                ..definition("MZXW6YTBOI")
+            },
+        )
+        .unwrap();
+
+        assert!(
+            yubikey.sent()[0].ends_with(b"\x7a\x04\x00\x00\x01\x02"),
+            "assert initial counter is sent"
+        )
+    }
+
+    #[test]
+    fn add_credential_sends_padded_key() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        add_credential(&yubikey, &definition("MZXW6YTBOI")).unwrap();
+
+        assert_eq!(
+            b"\x00\x01\x00\x00\x17\x71\x03new\x73\x10\x22\x08foobar\0\0\0\0\0\0\0\0".as_slice(),
+            yubikey.sent()[0],
+            "assert credential is sent with padded key"
+        )
+    }
+
+    #[test]
+    fn add_credential_sends_algorithm_with_unpadded_key() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        add_credential(
+            &yubikey,
+            &definition("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA===="),
+        )
+        .unwrap();
+
+        assert_eq!(
+            [
+                b"\x00\x01\x00\x00\x29\x71\x03new\x73\x22\x22\x08".as_slice(),
+                b"12345678901234567890123456789012"
+            ]
+            .concat(),
+            yubikey.sent()[0],
+            "assert SHA256 key is sent as is"
+        )
+    }
+
+    #[test_case(CredentialDefinition { name: "a".repeat(65), ..definition("MZXW6YTBOI") }, |e| matches!(e, Error::NameTooLong); "fails on name longer than 64 bytes")]
+    #[test_case(CredentialDefinition { name: "ä".repeat(33), ..definition("MZXW6YTBOI") }, |e| matches!(e, Error::NameTooLong); "counts name length in bytes")]
+    #[test_case(CredentialDefinition { digits: 5, ..definition("MZXW6YTBOI") }, |e| matches!(e, Error::InvalidDigits); "fails on too few digits")]
+    #[test_case(CredentialDefinition { digits: 9, ..definition("MZXW6YTBOI") }, |e| matches!(e, Error::InvalidDigits); "fails on too many digits")]
+    fn add_credential_fails_before_sending(
+        definition: CredentialDefinition,
+        expected: fn(&Error) -> bool,
+    ) {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        assert!(
+            add_credential(&yubikey, &definition).is_err_and(|e| expected(&e)),
+            "assert invalid definition is rejected"
+        );
+        assert!(yubikey.sent().is_empty(), "assert nothing is sent")
+    }
+
+    #[test]
+    fn add_credential_accepts_name_of_64_bytes() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        add_credential(
+            &yubikey,
+            &CredentialDefinition {
+                name: "a".repeat(64),
+                ..definition("MZXW6YTBOI")
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            b"\x71\x40".as_slice(),
+            &yubikey.sent()[0][5..7],
+            "assert name is sent with its length"
+        )
+    }
+
+    #[test_case(Algorithm::Sha1, 64, 64; "keeps SHA1 key of block size")]
+    #[test_case(Algorithm::Sha1, 65, 20; "hashes longer SHA1 key")]
+    #[test_case(Algorithm::Sha256, 65, 32; "hashes longer SHA256 key")]
+    #[test_case(Algorithm::Sha512, 128, 128; "keeps SHA512 key of block size")]
+    #[test_case(Algorithm::Sha512, 129, 64; "hashes longer SHA512 key")]
+    fn shorten_key_hashes_keys_above_block_size(
+        algorithm: Algorithm,
+        length: usize,
+        expected: usize,
+    ) {
+        assert_eq!(
+            expected,
+            shorten_key(vec![0x42; length], algorithm).len(),
+            "assert key is hashed with the credential algorithm"
+        )
+    }
+
+    // RFC 6238 test vectors at 59 seconds, which the device truncates to 0x41397eea
+    // for SHA1 and to 0x2c78e04e for SHA256 before they are reduced to eight digits
+    #[test_case(b"\x76\x05\x08\x41\x39\x7e\xea\x90\x00", 94_287_082; "reads SHA1 code")]
+    #[test_case(b"\x76\x05\x08\x2c\x78\xe0\x4e\x90\x00", 46_119_246; "reads SHA256 code")]
+    fn calculate_reads_rfc_6238_codes(response: &[u8], expected: u32) {
+        let yubikey = MockCard::new(&[response]);
+
+        let code = calculate(&yubikey, "rfc", 59).unwrap();
+
+        assert_eq!(
+            (expected, 8),
+            (code.value, code.digits),
+            "assert truncated code of the device is reduced to its digits"
+        );
+        assert!(
+            yubikey.sent()[0].ends_with(b"\x74\x08\x00\x00\x00\x00\x00\x00\x00\x01"),
+            "assert time step is the challenge"
+        )
+    }
+
+    #[test]
+    fn add_credential_fails_on_invalid_secret() {
+        let yubikey = MockCard::new(&[]);
+
+        let result = add_credential(&yubikey, &definition("not base32!"));
+
+        assert!(
+            matches!(result, Err(Error::InvalidSecret)),
+            "assert invalid secret is reported"
+        );
+        assert!(
+            yubikey.sent().is_empty(),
+            "assert invalid secret is not sent to the device"
+        )
+    }
+
+    #[test]
+    fn add_credential_fails_on_rejection() {
+        let yubikey = MockCard::new(&[b"\x6a\x84"]);
+
+        let result = add_credential(&yubikey, &definition("MZXW6YTBOI"));
+
+        assert!(
+            matches!(result, Err(Error::Rejected)),
+            "assert device rejection is reported"
+        )
+    }
+
+    #[test]
+    fn delete_credential_sends_exact_name() {
+        let yubikey = MockCard::new(&[LIST_RESPONSE, b"\x90\x00"]);
+
+        delete_credential(&yubikey, "time").unwrap();
+
+        assert_eq!(
+            b"\x00\x02\x00\x00\x06\x71\x04time".as_slice(),
+            yubikey.sent()[1],
+            "assert deletion is requested for exact name"
+        )
+    }
+
+    #[test]
+    fn delete_credential_fails_on_no_match() {
+        let yubikey = MockCard::new(&[LIST_RESPONSE]);
+
+        let result = delete_credential(&yubikey, "tim");
+
+        assert!(
+            matches!(result, Err(Error::NoMatchingCredential)),
+            "assert partial name does not match"
+        );
+        assert_eq!(1, yubikey.sent().len(), "assert nothing is deleted")
+    }
+
+    #[test]
+    fn delete_credential_fails_on_ambiguous_match() {
+        let yubikey = MockCard::new(&[b"\x72\x05\x21time\x72\x05\x21time\x90\x00"]);
+
+        let result = delete_credential(&yubikey, "time");
+
+        assert!(
+            matches!(result, Err(Error::TooManyMatchingCredentials(_))),
+            "assert duplicate names are refused"
+        );
+        assert_eq!(1, yubikey.sent().len(), "assert nothing is deleted")
+    }
+
+    #[test_case("T", vec!["counter", "time"]; "returns all matching credentials")]
+    #[test_case("x", vec![]; "returns no credentials without match")]
+    fn filter_listing_keeps_matching_credentials(search_term: &str, expected: Vec<&str>) {
+        let listing = list_all_credentials(&MockCard::new(&[LIST_RESPONSE])).unwrap();
+
+        assert_eq!(
+            expected,
+            filter_listing(listing, search_term, MatchOptions::default()).names(),
+            "assert only matching credentials are listed"
+        )
+    }
+
+    #[test]
+    fn list_all_credentials_skips_unparseable_entry() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x08\x11counter\x72\x03\x21\xff\xfe\x72\x00\x72\x05\x21time\x90\x00",
+        ]);
+
+        let listing = list_all_credentials(&yubikey).unwrap();
+
+        assert_eq!(
+            (vec![String::from("counter"), String::from("time")], 2),
+            (listing.clone().names(), listing.unparseable),
+            "assert readable credentials are listed and the others counted"
+        )
+    }
+
+    #[test_case(OathType::Totp, vec!["time"]; "keeps totp credentials")]
+    #[test_case(OathType::Hotp, vec!["counter"]; "keeps hotp credentials")]
+    fn filter_listing_by_type_keeps_credentials_of_type(oath_type: OathType, expected: Vec<&str>) {
+        let listing = list_all_credentials(&MockCard::new(&[LIST_RESPONSE])).unwrap();
+
+        assert_eq!(
+            expected,
+            filter_listing_by_type(listing, oath_type).names(),
+            "assert only credentials of the type are listed"
+        )
+    }
+
+    #[test_case("time", true; "finds credential with equal name")]
+    #[test_case("tim", false; "ignores credential containing name")]
+    #[test_case("Time", false; "compares case sensitive")]
+    fn credential_exists_compares_exactly(name: &str, expected: bool) {
+        let yubikey = MockCard::new(&[LIST_RESPONSE]);
+
+        assert_eq!(
+            expected,
+            credential_exists(&yubikey, name).unwrap(),
+            "assert only an exact name counts as existing"
+        )
+    }
+
+    #[test_case(b"\x79\x03\x05\x07\x01\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x90\x00"; "refuses current firmware")]
+    #[test_case(b"\x79\x03\x04\x03\x05\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x90\x00"; "refuses older firmware")]
+    #[test_case(b""; "refuses unknown firmware")]
+    fn check_touch_update_refuses_fixed_policy(select_response: &[u8]) {
+        assert!(
+            matches!(
+                check_touch_update(select_response),
+                Err(Error::TouchPolicyFixed)
+            ),
+            "assert firmware without in-place touch changes is refused"
+        )
+    }
+
+    #[test_case("time", Some("time"); "finds exact name")]
+    #[test_case("tim", None; "fails on partial name")]
+    fn credential_info_matches_exact_name(name: &str, expected: Option<&str>) {
+        let yubikey = MockCard::new(&[
+            LIST_RESPONSE,
+            b"\x71\x07counter\x77\x01\x06\x71\x04time\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+
+        assert_eq!(
+            expected.map(String::from),
+            credential_info(&yubikey, name, 59).ok().map(|cred| cred.id),
+            "assert only the credential with the exact name is returned"
+        )
+    }
+
+    #[test]
+    fn rename_credential_sends_both_names() {
+        let yubikey = MockCard::new(&[LIST_RESPONSE, b"\x90\x00"]);
+
+        rename_credential(&yubikey, "time", "clock").unwrap();
+
+        assert_eq!(
+            b"\x00\x05\x00\x00\x0d\x71\x04time\x71\x05clock".as_slice(),
+            yubikey.sent()[1],
+            "assert renaming is requested from old to new name"
+        )
+    }
+
+    #[test_case("tim", "clock", |e| matches!(e, Error::NoMatchingCredential); "fails on no match")]
+    #[test_case("time", "counter", |e| matches!(e, Error::CredentialExists); "fails on existing name")]
+    fn rename_credential_fails(from: &str, to: &str, expected: fn(&Error) -> bool) {
+        let yubikey = MockCard::new(&[LIST_RESPONSE]);
+
+        let result = rename_credential(&yubikey, from, to);
+
+        assert!(
+            matches!(result, Err(ref e) if expected(e)),
+            "assert renaming is refused"
+        );
+        assert_eq!(1, yubikey.sent().len(), "assert nothing is renamed")
+    }
+
+    #[test]
+    fn rename_credential_fails_on_name_longer_than_64_bytes() {
+        let yubikey = MockCard::new(&[LIST_RESPONSE]);
+
+        let result = rename_credential(&yubikey, "time", &"a".repeat(65));
+
+        assert!(
+            matches!(result, Err(Error::NameTooLong)),
+            "assert overlong name is refused"
+        );
+        assert!(yubikey.sent().is_empty(), "assert nothing is sent")
+    }
+
+    #[test]
+    fn reset_sends_reset_command() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        reset(&yubikey).unwrap();
+
+        assert_eq!(
+            vec![b"\x00\x04\xde\xad".to_vec()],
+            yubikey.sent(),
+            "assert reset is requested"
+        )
+    }
+
+    #[test]
+    fn reset_fails_on_rejection() {
+        let yubikey = MockCard::new(&[b"\x6d\x00"]);
+
+        assert!(
+            matches!(reset(&yubikey), Err(Error::Rejected)),
+            "assert rejected reset is reported"
+        )
+    }
+
+    const LOCKED_SELECT_RESPONSE: &[u8] =
+        b"\x79\x03\x05\x04\x03\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x74\x08\x11\x22\x33\x44\x55\x66\x77\x88\x7b\x01\x01\x90\x00";
+
+    // RFC 6238 appendix B, whose seeds are as long as the output of their hash
+    #[test_case(Algorithm::Sha1, 59, 94_287_082; "sha1 at 59")]
+    #[test_case(Algorithm::Sha1, 1_111_111_109, 7_081_804; "sha1 at 1111111109")]
+    #[test_case(Algorithm::Sha1, 1_111_111_111, 14_050_471; "sha1 at 1111111111")]
+    #[test_case(Algorithm::Sha1, 1_234_567_890, 89_005_924; "sha1 at 1234567890")]
+    #[test_case(Algorithm::Sha1, 2_000_000_000, 69_279_037; "sha1 at 2000000000")]
+    #[test_case(Algorithm::Sha1, 20_000_000_000, 65_353_130; "sha1 at 20000000000")]
+    #[test_case(Algorithm::Sha256, 59, 46_119_246; "sha256 at 59")]
+    #[test_case(Algorithm::Sha256, 1_111_111_109, 68_084_774; "sha256 at 1111111109")]
+    #[test_case(Algorithm::Sha256, 20_000_000_000, 77_737_706; "sha256 at 20000000000")]
+    #[test_case(Algorithm::Sha512, 59, 90_693_936; "sha512 at 59")]
+    #[test_case(Algorithm::Sha512, 1_111_111_109, 25_091_201; "sha512 at 1111111109")]
+    #[test_case(Algorithm::Sha512, 20_000_000_000, 47_863_826; "sha512 at 20000000000")]
+    fn calculate_totp_matches_rfc_vectors(algorithm: Algorithm, time: u64, expected: u32) {
+        let seed = match algorithm {
+            Algorithm::Sha1 => b"12345678901234567890".to_vec(),
+            Algorithm::Sha256 => b"12345678901234567890123456789012".to_vec(),
+            Algorithm::Sha512 => b"1234567890".repeat(7)[..64].to_vec(),
+        };
+
+        assert_eq!(
+            expected,
+            calculate_totp(&seed, algorithm, 8, DEFAULT_PERIOD, time).value,
+            "assert code is calculated as in the RFC"
+        )
+    }
+
+    // RFC 4226 appendix D
+    #[test_case(0, 755_224; "counter 0")]
+    #[test_case(1, 287_082; "counter 1")]
+    #[test_case(2, 359_152; "counter 2")]
+    #[test_case(3, 969_429; "counter 3")]
+    #[test_case(4, 338_314; "counter 4")]
+    #[test_case(5, 254_676; "counter 5")]
+    #[test_case(6, 287_922; "counter 6")]
+    #[test_case(7, 162_583; "counter 7")]
+    #[test_case(8, 399_871; "counter 8")]
+    #[test_case(9, 520_489; "counter 9")]
+    fn calculate_hotp_at_matches_rfc_vectors(counter: u64, expected: u32) {
+        assert_eq!(
+            expected,
+            calculate_hotp_at(b"12345678901234567890", Algorithm::Sha1, 6, counter).value,
+            "assert code is calculated as in the RFC"
+        )
+    }
+
+    #[test]
+    fn derive_key_uses_pbkdf2() {
+        assert_eq!(
+            b"\xed\x1b\x5a\x43\xd3\xa8\x65\x04\xdd\x13\xc9\xda\x76\x06\xbd\x35",
+            &derive_key("password", b"\x01\x02\x03\x04\x05\x06\x07\x08"),
+            "assert key is derived from password and salt"
+        )
+    }
+
+    #[test]
+    fn unlock_answers_device_challenge() {
+        let yubikey = MockCard::new(&[b"\x6a\x80"]);
+
+        let _ = unlock(&yubikey, LOCKED_SELECT_RESPONSE, "password");
+
+        assert!(
+            yubikey.sent()[0].starts_with(
+                b"\x00\xa3\x00\x00\x20\x75\x14\xa6\xbf\xd0\x51\x46\x18\x7f\xd0\xc4\x29\xec\xda\x8d\xea\x49\x0e\xde\x33\x3c\x79\x74\x08"
+            ),
+            "assert validation answers the challenge of the device"
+        )
+    }
+
+    #[test]
+    fn unlock_fails_on_wrong_password() {
+        let yubikey = MockCard::new(&[b"\x6a\x80"]);
+
+        let result = unlock(&yubikey, LOCKED_SELECT_RESPONSE, "wrong");
+
+        assert!(
+            matches!(result, Err(Error::WrongPassword)),
+            "assert wrong password is reported"
+        )
+    }
+
+    #[test]
+    fn set_password_sends_derived_key() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        set_password(&yubikey, LOCKED_SELECT_RESPONSE, Some("password")).unwrap();
+
+        let apdu = &yubikey.sent()[0];
+        assert!(
+            apdu.starts_with(b"\x00\x03\x00\x00\x33\x73\x11\x21\xed\x1b\x5a\x43\xd3\xa8\x65\x04\xdd\x13\xc9\xda\x76\x06\xbd\x35\x74\x08"),
+            "assert key is derived from password and device salt"
+        );
+        assert_eq!(
+            [
+                b"\x75\x14".as_slice(),
+                &hmac_sha1(&apdu[8..24], &apdu[26..34])
+            ]
+            .concat(),
+            apdu[34..],
+            "assert challenge is answered with the new key"
+        )
+    }
+
+    #[test]
+    fn set_password_clears_password() {
+        let yubikey = MockCard::new(&[b"\x90\x00"]);
+
+        set_password(&yubikey, LOCKED_SELECT_RESPONSE, None).unwrap();
+
+        assert_eq!(
+            vec![b"\x00\x03\x00\x00\x02\x73\x00".to_vec()],
+            yubikey.sent(),
+            "assert empty key is sent"
+        )
+    }
+
+    #[test]
+    fn set_password_requires_current_password() {
+        let yubikey = MockCard::new(&[b"\x69\x82"]);
+
+        assert!(
+            matches!(
+                set_password(&yubikey, LOCKED_SELECT_RESPONSE, Some("new")),
+                Err(Error::AuthRequired)
+            ),
+            "assert locked application is reported"
+        )
+    }
+
+    #[test]
+    fn unlock_skips_unprotected_application() {
+        let yubikey = MockCard::new(&[]);
+
+        unlock(&yubikey, b"\x79\x03\x05\x04\x03\x90\x00", "password").unwrap();
+
+        assert!(
+            yubikey.sent().is_empty(),
+            "assert nothing is sent without challenge"
+        )
+    }
+
+    #[test_case("GitHub:alice@example.com", OathType::Totp, Some("GitHub"), "alice@example.com"; "splits issuer prefix")]
+    #[test_case("alice@example.com", OathType::Totp, None, "alice@example.com"; "has no issuer without prefix")]
+    #[test_case("GitHub:alice:work", OathType::Totp, Some("GitHub"), "alice:work"; "keeps colon inside account")]
+    #[test_case("60/GitHub:alice", OathType::Totp, Some("GitHub"), "alice"; "ignores period prefix")]
+    #[test_case("60/GitHub:alice", OathType::Hotp, Some("60/GitHub"), "alice"; "keeps slash of hotp credential")]
+    #[test_case(":alice", OathType::Totp, None, ":alice"; "ignores empty issuer")]
+    fn split_name_separates_issuer_and_account(
+        cred: &str,
+        oath_type: OathType,
+        issuer: Option<&str>,
+        account: &str,
+    ) {
+        assert_eq!(
+            (issuer, account),
+            split_name(cred, oath_type),
+            "assert name is split into issuer and account"
+        )
+    }
+
+    #[test]
+    fn list_credentials_fails_when_locked() {
+        let yubikey = MockCard::new(&[b"\x69\x82"]);
+
+        assert!(
+            matches!(list_credentials(&yubikey), Err(Error::AuthRequired)),
+            "assert locked application requires authentication"
+        )
+    }
+
+    #[test]
+    fn list_credentials_detailed_combines_list_and_calculation() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x22GitHub:alice\x72\x08\x11counter\x72\x06\x21Vault\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x08\x00\x0b\x76\x3a\x71\x07counter\x77\x01\x06\x71\x05Vault\x7c\x01\x06\x90\x00",
+        ]);
+
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+
+        assert_eq!(
+            vec![
+                Credential {
+                    id: String::from("GitHub:alice"),
+                    issuer: Some(String::from("GitHub")),
+                    name: String::from("alice"),
+                    period: Some(30),
+                    digits: Some(8),
+                    algorithm: Algorithm::Sha256,
+                    oath_type: OathType::Totp,
+                    touch: false,
+                },
+                Credential {
+                    id: String::from("counter"),
+                    issuer: None,
+                    name: String::from("counter"),
+                    period: None,
+                    digits: Some(6),
+                    algorithm: Algorithm::Sha1,
+                    oath_type: OathType::Hotp,
+                    touch: false,
+                },
+                Credential {
+                    id: String::from("Vault"),
+                    issuer: None,
+                    name: String::from("Vault"),
+                    period: Some(30),
+                    digits: Some(6),
+                    algorithm: Algorithm::Sha1,
+                    oath_type: OathType::Totp,
+                    touch: true,
+                },
+            ],
+            creds,
+            "assert credential metadata is read from list and calculation"
+        );
+        assert_eq!(
+            b"\x00\xa4\x00\x01\x0a\x74\x08\x00\x00\x00\x00\x00\x00\x00\x01".as_slice(),
+            yubikey.sent()[1],
+            "assert calculation uses current time step"
+        )
+    }
+
+    #[test]
+    fn calculate_fuzzy_signals_touch() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x06\x21Vault\x90\x00",
+            b"\x71\x05Vault\x7c\x01\x06\x90\x00",
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+        let mut touched = false;
+
+        calculate_fuzzy(
+            &yubikey,
+            &creds,
+            "vault",
+            MatchOptions::default(),
+            59,
+            0,
+            || touched = true,
+        )
+        .unwrap();
+
+        assert!(touched, "assert touch requirement is signalled")
+    }
+
+    #[test_case(1, b"\x00\x00\x00\x00\x00\x00\x00\x02"; "shifts to next step")]
+    #[test_case(-1, b"\x00\x00\x00\x00\x00\x00\x00\x00"; "shifts to previous step")]
+    fn calculate_fuzzy_shifts_step(step_offset: i64, challenge: &[u8]) {
+        let yubikey = MockCard::new(&[
+            b"\x72\x06\x21Vault\x90\x00",
+            b"\x71\x05Vault\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+
+        calculate_fuzzy(
+            &yubikey,
+            &creds,
+            "vault",
+            MatchOptions::default(),
+            59,
+            step_offset,
+            || {},
+        )
+        .unwrap();
+
+        assert!(
+            yubikey.sent()[2].ends_with(&[b"\x74\x08".as_slice(), challenge].concat()),
+            "assert time step is shifted by whole periods"
+        )
+    }
+
+    #[test_case(u64::MAX, 1; "fails beyond end of time")]
+    #[test_case(59, i64::MAX; "fails on huge step offset")]
+    #[test_case(59, -5; "fails before epoch")]
+    fn calculate_fuzzy_refuses_out_of_range_time(time: u64, step_offset: i64) {
+        let yubikey = MockCard::new(&[
+            b"\x72\x06\x21Vault\x90\x00",
+            b"\x71\x05Vault\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+
+        let result = calculate_fuzzy(
+            &yubikey,
+            &creds,
+            "vault",
+            MatchOptions::default(),
+            time,
+            step_offset,
+            || {},
+        );
+
+        assert!(
+            matches!(result, Err(Error::TimeOutOfRange)),
+            "assert time step out of range is refused"
+        );
+        assert_eq!(2, yubikey.sent().len(), "assert nothing is calculated")
+    }
+
+    #[test_case(yubikey::Version(5, 7, 1), Some(64); "knows slots of current firmware")]
+    #[test_case(yubikey::Version(5, 4, 3), Some(32); "knows slots of older firmware")]
+    #[test_case(yubikey::Version(3, 4, 0), None; "does not know slots of unsupported firmware")]
+    fn max_credentials_depends_on_firmware(version: yubikey::Version, max: Option<usize>) {
+        assert_eq!(
+            max,
+            max_credentials(&version),
+            "assert number of slots is derived from the firmware version"
+        )
+    }
+
+    #[test_case(b"\x79\x03\x05\x04\x03\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x90\x00", Some(yubikey::Version(5, 4, 3)); "reads version of unprotected application")]
+    #[test_case(b"\x79\x03\x04\x03\x05\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x74\x08\x01\x02\x03\x04\x05\x06\x07\x08\x7b\x01\x21\x90\x00", Some(yubikey::Version(4, 3, 5)); "reads version of protected application")]
+    #[test_case(b"\x71\x08\x01\x02\x03\x04\x05\x06\x07\x08\x90\x00", None; "reads no version without tag")]
+    fn applet_version_parses_select_response(
+        select_response: &[u8],
+        version: Option<yubikey::Version>,
+    ) {
+        assert_eq!(
+            version,
+            applet_version(select_response),
+            "assert version is read from the select response"
+        )
+    }
+
+    #[test]
+    fn require_touch_calculates_touch_credential() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x07\x21GitHub\x72\x06\x21Vault\x90\x00",
+            b"\x71\x06GitHub\x76\x05\x06\x41\x39\x7e\xea\x71\x05Vault\x7c\x01\x06\x90\x00",
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+        let mut touched = false;
+
+        require_touch(&yubikey, 59, || touched = true).unwrap();
+
+        assert!(touched, "assert touch requirement is signalled");
+        assert!(
+            yubikey.sent()[2].windows(5).any(|w| w == b"Vault"),
+            "assert credential requiring a touch is calculated"
+        )
+    }
+
+    #[test]
+    fn require_touch_fails_without_touch_credential() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x07\x21GitHub\x90\x00",
+            b"\x71\x06GitHub\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+
+        assert!(
+            matches!(
+                require_touch(&yubikey, 59, || {}),
+                Err(Error::NoTouchCredential)
+            ),
+            "assert missing touch credential is reported"
+        )
+    }
+
+    #[test_case("Steam:alice", true; "detects steam credential")]
+    #[test_case("GitHub:alice", false; "detects regular credential")]
+    fn calculate_detects_steam(cred: &str, steam: bool) {
+        let yubikey = MockCard::new(&[b"\x76\x05\x05\x41\x39\x7e\xea\x90\x00"]);
+
+        assert_eq!(
+            steam,
+            calculate(&yubikey, cred, 59).unwrap().steam,
+            "assert steam credentials are detected by issuer"
+        )
+    }
+
+    #[test_case("AWS:alice", b"\x00\x00\x00\x00\x00\x00\x00\x03", 30; "uses default period")]
+    #[test_case("60/AWS:alice", b"\x00\x00\x00\x00\x00\x00\x00\x01", 60; "uses period of prefix")]
+    #[test_case("AWS:alice/bob", b"\x00\x00\x00\x00\x00\x00\x00\x03", 30; "ignores slash in name")]
+    fn calculate_uses_period_of_credential(cred: &str, challenge: &[u8], period: u64) {
+        let yubikey = MockCard::new(&[b"\x76\x05\x06\x41\x39\x7e\xea\x90\x00"]);
+
+        let code = calculate(&yubikey, cred, 119).unwrap();
+
+        assert!(
+            yubikey.sent()[0].ends_with(challenge),
+            "assert time step is derived from the period"
+        );
+        assert_eq!(
+            Some(period),
+            code.period,
+            "assert period of the credential is returned"
+        )
+    }
+
+    #[test]
+    fn calculate_exact_succeeds_on_full_name() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x21GitHub:alice\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+            b"\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+
+        assert!(
+            calculate_exact(&yubikey, &creds, "GitHub:alice", 59, 0, || {}).is_ok(),
+            "assert full name matches"
+        )
+    }
+
+    #[test_case("GitHub"; "fails on substring")]
+    #[test_case("github:alice"; "fails on different case")]
+    fn calculate_exact_fails_on_partial_name(name: &str) {
+        let yubikey = MockCard::new(&[
+            b"\x72\x0d\x21GitHub:alice\x90\x00",
+            b"\x71\x0cGitHub:alice\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+
+        assert!(
+            matches!(
+                calculate_exact(&yubikey, &creds, name, 59, 0, || {}),
+                Err(Error::NoMatchingCredential)
+            ),
+            "assert partial name does not match"
+        )
+    }
+
+    #[test]
+    fn resolve_fuzzy_returns_full_name() {
+        let names = [String::from("GitHub:alice"), String::from("Vault")];
+
+        assert_eq!(
+            "GitHub:alice",
+            resolve_fuzzy(&names, "github", MatchOptions::default()).unwrap(),
+            "assert full name of the matching credential is returned"
+        )
+    }
+
+    #[test]
+    fn calculate_fuzzy_reports_all_candidates() {
+        let yubikey = MockCard::new(&[
+            b"\x72\x07\x21github\x72\x07\x21gitlab\x72\x06\x21Vault\x90\x00",
+            b"\x71\x06github\x76\x05\x06\x41\x39\x7e\xea\x71\x06gitlab\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+        ]);
+        let creds = list_credentials_detailed(&yubikey, 59).unwrap();
+
+        let result = calculate_fuzzy(
+            &yubikey,
+            &creds,
+            "git",
+            MatchOptions::default(),
+            59,
+            0,
+            || {},
+        );
+
+        assert!(
+            matches!(result, Err(Error::TooManyMatchingCredentials(candidates)) if candidates == vec!["github", "gitlab"]),
+            "assert every matching credential is a candidate"
+        );
+        assert_eq!(2, yubikey.sent().len(), "assert no code is calculated")
+    }
+
+    #[test]
+    fn calculate_fails_on_touch_timeout() {
+        let yubikey = MockCard::new(&[b"\x69\x85"]);
+
+        assert!(
+            matches!(calculate(&yubikey, "Vault", 59), Err(Error::TouchTimeout)),
+            "assert missing touch is reported"
+        )
+    }
+
+    #[test_case("count", "counter"; "finds credential by substring")]
+    #[test_case("TIME", "time"; "ignores case")]
+    fn find_match_succeeds(search_term: &str, expected: &str) {
+        let creds = list_credentials(&MockCard::new(&[LIST_RESPONSE])).unwrap();
+        assert_eq!(
+            expected,
+            find_match(&creds, search_term, MatchOptions::default()).unwrap(),
+            "assert search term matches expected credential"
+        )
+    }
+
+    #[test_case(&[b"\x90"]; "fails on short response")]
+    #[test_case(&[b"\x72\x05\x21time\x61\x05", b""]; "fails on short remaining response")]
+    fn list_credentials_refuses_truncated_status(responses: &[&[u8]]) {
+        assert!(
+            matches!(
+                list_credentials(&MockCard::new(responses)),
+                Err(Error::Yubikey)
+            ),
+            "assert response without status word is an error instead of a panic"
+        )
+    }
+
+    #[test]
+    fn find_listed_returns_matching_credential() {
+        let creds = list_credentials_detailed(
+            &MockCard::new(&[
+                b"\x72\x05\x21time\x90\x00",
+                b"\x71\x04time\x7c\x01\x06\x90\x00",
+            ]),
+            59,
+        )
+        .unwrap();
+
+        let cred = find_listed(&creds, "tim", MatchOptions::default()).unwrap();
+
+        assert_eq!(
+            ("time", true),
+            (cred.id.as_str(), cred.touch),
+            "assert listed credential is found with its touch requirement"
+        )
+    }
+
+    #[test_case("git", "github", Some(Rank::Prefix); "ranks prefix")]
+    #[test_case("hub", "github", Some(Rank::Substring); "ranks substring")]
+    #[test_case("gthb", "github", Some(Rank::Subsequence); "ranks subsequence")]
+    #[test_case("bug", "github", None; "does not rank characters out of order")]
+    fn rank_orders_kind_of_match(search_term: &str, cred: &str, expected: Option<Rank>) {
+        assert_eq!(
+            expected,
+            rank(cred, search_term, MatchOptions::default()),
+            "assert match is ranked by its kind"
+        )
+    }
+
+    #[test_case("60/AWS:alice", Some(60), "AWS:alice"; "splits period prefix")]
+    #[test_case("AWS:alice", None, "AWS:alice"; "keeps name without prefix")]
+    #[test_case("AWS/alice", None, "AWS/alice"; "keeps name with other prefix")]
+    fn split_period_reads_prefix(cred: &str, period: Option<u64>, name: &str) {
+        assert_eq!(
+            (period, name),
+            split_period(cred),
+            "assert period prefix is split off the name"
+        )
+    }
+
+    #[test_case("alice", Some(Rank::Prefix); "matches start of account")]
+    #[test_case("git", Some(Rank::Prefix); "matches start of issuer")]
+    #[test_case("hub:al", Some(Rank::Substring); "matches across issuer and account")]
+    #[test_case("lice", Some(Rank::Substring); "matches inside account")]
+    fn rank_matches_issuer_and_account(search_term: &str, expected: Option<Rank>) {
+        assert_eq!(
+            expected,
+            rank("GitHub:alice", search_term, MatchOptions::default()),
+            "assert issuer and account are matched on their own"
+        )
+    }
+
+    #[test]
+    fn find_match_ignores_period_prefix() {
+        let creds = vec![String::from("60/AWS:alice"), String::from("myaws")];
+
+        assert_eq!(
+            "60/AWS:alice",
+            find_match(&creds, "aws", MatchOptions::default()).unwrap(),
+            "assert name after the period prefix is matched as prefix"
+        )
+    }
+
+    #[test]
+    fn find_match_prefers_prefix_over_substring() {
+        let creds = vec![String::from("mygoogleaccount"), String::from("google")];
+
+        assert_eq!(
+            "google",
+            find_match(&creds, "goo", MatchOptions::default()).unwrap(),
+            "assert prefix match wins over substring match"
+        )
+    }
+
+    #[test]
+    fn find_match_fails_on_tie_of_best_matches() {
+        let creds = vec![
+            String::from("github"),
+            String::from("mygit"),
+            String::from("gitlab"),
+        ];
+
+        assert!(
+            matches!(find_match(&creds, "git", MatchOptions::default()), Err(Error::TooManyMatchingCredentials(candidates)) if candidates == vec!["github", "gitlab"]),
+            "assert tied best matches are the candidates"
+        )
+    }
+
+    #[test_case(0x41397eea, 6, false, 287_082; "reduces to six digits")]
+    #[test_case(0x41397eea, 8, false, 94_287_082; "reduces to eight digits")]
+    #[test_case(0x41397eea, 5, true, 0x41397eea; "keeps full value of steam code")]
+    fn code_from_truncated_reduces_value(value: u32, digits: u8, steam: bool, expected: u32) {
+        assert_eq!(
+            expected,
+            Code::from_truncated(value, digits, steam, None).value,
+            "assert only the last digits of the truncated value are kept"
+        )
+    }
+
+    #[test_case(true, true; "matches other case when case insensitive")]
+    #[test_case(false, false; "fails on other case when case sensitive")]
+    fn find_match_respects_case_option(case_insensitive: bool, matches: bool) {
+        let creds = vec![String::from("GitHub")];
+        let options = MatchOptions { case_insensitive };
+
+        assert_eq!(
+            matches,
+            find_match(&creds, "github", options).is_ok(),
+            "assert case is only ignored if requested"
+        )
+    }
+
+    #[test]
+    fn calculate_all_flags_touch_credentials() {
+        let yubikey = MockCard::new(&[
+            b"\x71\x06GitHub\x76\x05\x06\x41\x39\x7e\xea\x71\x05Vault\x7c\x01\x06\x90\x00",
+        ]);
+
+        assert_eq!(
+            vec![
+                CredentialCode {
+                    name: String::from("GitHub"),
+                    code: Some(Code {
+                        value: 287_082,
+                        digits: 6,
+                        steam: false,
+                        period: Some(30)
+                    }),
+                    touch: false,
+                    oath_type: OathType::Totp
+                },
+                CredentialCode {
+                    name: String::from("Vault"),
+                    code: None,
+                    touch: true,
+                    oath_type: OathType::Totp
+                },
+            ],
+            calculate_all(&yubikey, 59).unwrap(),
+            "assert touch credentials are flagged instead of calculated"
+        );
+        assert_eq!(1, yubikey.sent().len(), "assert no touch is waited for")
+    }
+
+    #[test]
+    fn calculate_all_recalculates_other_periods() {
+        let yubikey = MockCard::new(&[
+            b"\x71\x0960/GitHub\x76\x05\x06\x41\x39\x7e\xea\x90\x00",
+            b"\x76\x05\x06\x00\x0b\x76\x3a\x90\x00",
+        ]);
+
+        let codes = calculate_all(&yubikey, 119).unwrap();
+
+        assert_eq!(
+            Some(0x000b763a),
+            codes[0].code.as_ref().map(|code| code.value),
+            "assert code is calculated again for its own period"
+        );
+        assert!(
+            yubikey.sent()[1].ends_with(b"\x74\x08\x00\x00\x00\x00\x00\x00\x00\x01"),
+            "assert time step of the credential period is the challenge"
+        )
+    }
 }
@@ -0,0 +1,232 @@
+use crate::base32;
+use crate::oath::{self, Algorithm, Credential, CredentialDefinition, OathType};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Malformed,
+    MissingSecret,
+    InvalidSecret,
+}
+
+// the secret cannot be read from the device, so the URI only restores the metadata
+pub fn format(cred: &Credential) -> String {
+    let oath_type = match cred.oath_type {
+        OathType::Totp => "totp",
+        OathType::Hotp => "hotp",
+    };
+    let label = match &cred.issuer {
+        Some(issuer) => format!("{}:{}", encode(issuer), encode(&cred.name)),
+        None => encode(&cred.name),
+    };
+
+    let mut parameters = Vec::new();
+    if let Some(issuer) = &cred.issuer {
+        parameters.push(format!("issuer={}", encode(issuer)));
+    }
+    parameters.push(format!("algorithm={}", algorithm_name(cred.algorithm)));
+    if let Some(digits) = cred.digits {
+        parameters.push(format!("digits={}", digits));
+    }
+    if let Some(period) = cred.period {
+        parameters.push(format!("period={}", period));
+    }
+
+    format!("otpauth://{}/{}?{}", oath_type, label, parameters.join("&"))
+}
+
+pub fn parse(uri: &str) -> Result<CredentialDefinition, Error> {
+    let (oath_type, rest) = uri
+        .strip_prefix("otpauth://")
+        .and_then(|rest| rest.split_once('/'))
+        .ok_or(Error::Malformed)?;
+    let oath_type = match oath_type.to_lowercase().as_str() {
+        "totp" => OathType::Totp,
+        "hotp" => OathType::Hotp,
+        _ => return Err(Error::Malformed),
+    };
+    let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let label = decode(label).ok_or(Error::Malformed)?;
+
+    let mut secret = None;
+    let mut issuer = None;
+    let mut algorithm = Algorithm::Sha1;
+    let mut digits = 6;
+    let mut period = oath::DEFAULT_PERIOD;
+    let mut counter = 0;
+    for parameter in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = parameter.split_once('=').ok_or(Error::Malformed)?;
+        let value = decode(value).ok_or(Error::Malformed)?;
+        match key {
+            "secret" => secret = Some(value),
+            "issuer" => issuer = Some(value),
+            "algorithm" => {
+                algorithm = match value.to_uppercase().as_str() {
+                    "SHA1" => Algorithm::Sha1,
+                    "SHA256" => Algorithm::Sha256,
+                    "SHA512" => Algorithm::Sha512,
+                    _ => return Err(Error::Malformed),
+                }
+            }
+            "digits" => {
+                digits = value
+                    .parse()
+                    .ok()
+                    .filter(|d| (6..=8).contains(d))
+                    .ok_or(Error::Malformed)?
+            }
+            "period" => {
+                period = value
+                    .parse()
+                    .ok()
+                    .filter(|p| *p > 0)
+                    .ok_or(Error::Malformed)?
+            }
+            "counter" => counter = value.parse().map_err(|_| Error::Malformed)?,
+            // e.g. an image of the issuer
+            _ => {}
+        }
+    }
+
+    let secret = secret.ok_or(Error::MissingSecret)?;
+    if base32::decode(&secret).is_none() {
+        return Err(Error::InvalidSecret);
+    }
+
+    // the issuer parameter takes precedence over the prefix of the label
+    let (label_issuer, account) = match label.split_once(':') {
+        Some((issuer, account)) => (Some(issuer.trim()), account.trim_start()),
+        None => (None, label.as_str()),
+    };
+    let mut name = match issuer.as_deref().or(label_issuer) {
+        Some(issuer) => format!("{}:{}", issuer, account),
+        None => account.to_owned(),
+    };
+    // the device only knows the period from the name of the credential
+    if oath_type == OathType::Totp && period != oath::DEFAULT_PERIOD {
+        name = format!("{}/{}", period, name);
+    }
+
+    Ok(CredentialDefinition {
+        name,
+        secret,
+        algorithm,
+        digits,
+        oath_type,
+        counter,
+    })
+}
+
+fn algorithm_name(algorithm: Algorithm) -> &'static str {
+    match algorithm {
+        Algorithm::Sha1 => "SHA1",
+        Algorithm::Sha256 => "SHA256",
+        Algorithm::Sha512 => "SHA512",
+    }
+}
+
+// percent-encodes everything but the unreserved characters of RFC 3986
+fn encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut input_bytes = input.bytes();
+    while let Some(b) = input_bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [input_bytes.next()?, input_bytes.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    fn credential(issuer: Option<&str>, name: &str, oath_type: OathType) -> Credential {
+        Credential {
+            id: String::new(),
+            issuer: issuer.map(str::to_owned),
+            name: name.to_owned(),
+            period: match oath_type {
+                OathType::Totp => Some(30),
+                OathType::Hotp => None,
+            },
+            digits: Some(6),
+            algorithm: Algorithm::Sha256,
+            oath_type,
+            touch: false,
+        }
+    }
+
+    #[test_case(credential(Some("GitHub"), "alice@example.com", OathType::Totp), "otpauth://totp/GitHub:alice%40example.com?issuer=GitHub&algorithm=SHA256&digits=6&period=30"; "formats totp credential with issuer")]
+    #[test_case(credential(None, "counter", OathType::Hotp), "otpauth://hotp/counter?algorithm=SHA256&digits=6"; "formats hotp credential without issuer")]
+    #[test_case(credential(Some("My Bank"), "alice", OathType::Totp), "otpauth://totp/My%20Bank:alice?issuer=My%20Bank&algorithm=SHA256&digits=6&period=30"; "encodes reserved characters")]
+    fn format_builds_uri(cred: Credential, expected: &str) {
+        assert_eq!(
+            expected,
+            format(&cred),
+            "assert URI contains the metadata of the credential"
+        )
+    }
+
+    #[test_case("otpauth://totp/GitHub:alice%40example.com?secret=MZXW6YTBOI&issuer=GitHub", "GitHub:alice@example.com", OathType::Totp; "parses totp uri")]
+    #[test_case("otpauth://totp/alice?secret=MZXW6YTBOI&issuer=My%20Bank", "My Bank:alice", OathType::Totp; "prefixes encoded issuer")]
+    #[test_case("otpauth://totp/Example%3Aalice?secret=MZXW6YTBOI", "Example:alice", OathType::Totp; "takes issuer from encoded label")]
+    #[test_case("otpauth://totp/alice?secret=MZXW6YTBOI&period=60", "60/alice", OathType::Totp; "prefixes non default period")]
+    #[test_case("otpauth://hotp/counter?secret=MZXW6YTBOI&counter=5", "counter", OathType::Hotp; "parses hotp uri")]
+    fn parse_reads_name_and_type(uri: &str, name: &str, oath_type: OathType) {
+        let definition = parse(uri).unwrap();
+
+        assert_eq!(
+            (name, oath_type),
+            (definition.name.as_str(), definition.oath_type),
+            "assert name and type are read from the URI"
+        )
+    }
+
+    #[test]
+    fn parse_reads_parameters() {
+        let definition =
+            parse("otpauth://hotp/counter?secret=MZXW6YTBOI&algorithm=SHA512&digits=8&counter=5")
+                .unwrap();
+
+        assert_eq!(
+            CredentialDefinition {
+                name: String::from("counter"),
+                secret: String::from("MZXW6YTBOI"),
+                algorithm: Algorithm::Sha512,
+                digits: 8,
+                oath_type: OathType::Hotp,
+                counter: 5,
+            },
+            definition,
+            "assert query parameters are read"
+        )
+    }
+
+    #[test_case("otpauth://totp/alice", Error::MissingSecret; "fails without secret")]
+    #[test_case("otpauth://totp/alice?secret=MZXW6YTB0I", Error::InvalidSecret; "fails on invalid base32 secret")]
+    #[test_case("https://example.com/alice?secret=MZXW6YTBOI", Error::Malformed; "fails on other scheme")]
+    #[test_case("otpauth://motp/alice?secret=MZXW6YTBOI", Error::Malformed; "fails on unknown type")]
+    #[test_case("otpauth://totp/alice?secret=MZXW6YTBOI&digits=12", Error::Malformed; "fails on unsupported digits")]
+    #[test_case("otpauth://totp/alice%4?secret=MZXW6YTBOI", Error::Malformed; "fails on broken encoding")]
+    fn parse_fails(uri: &str, error: Error) {
+        assert_eq!(Err(error), parse(uri), "assert malformed URI is rejected")
+    }
+}
@@ -1,81 +1,825 @@
 use pcsc::*;
-use std::ffi::CStr;
+use serde::{Deserialize, Serialize};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::thread;
+use std::time::Duration;
 
 const YUBIKEY_NAME_FILTER: &str = "yubico yubikey";
+// NFC readers are named by their vendor, but usually after the contactless interface
+const NFC_NAME_FILTERS: &[&str] = &["nfc", "picc", "contactless", " cl "];
 
-const APDU_SELECT: &[u8] = b"\x00\xa4\x04\x00\x07\xa0\x00\x00\x05\x27\x21\x01";
+const APDU_SELECT: &[u8] = b"\x00\xa4\x04\x00";
+/// The application ID of the OATH application of a YubiKey.
+pub const OATH_AID: &[u8] = b"\xa0\x00\x00\x05\x27\x21\x01";
+const APDU_SELECT_OTP: &[u8] = b"\x00\xa4\x04\x00\x07\xa0\x00\x00\x05\x27\x20\x01";
+const APDU_GET_SERIAL: &[u8] = b"\x00\x01\x10\x00";
+const INS_OTP: u8 = 0x01;
+const HMAC_RESPONSE_LENGTH: usize = 20;
+pub const MAX_CHALLENGE_LENGTH: usize = 64;
+
+const CONNECT_ATTEMPTS: u32 = 3;
+const CONNECT_BACKOFF: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
 pub enum Error {
-    NoYubikey,
+    NoDevice,
     MoreThanOneYubikey,
-    Connection,
-    Transmission,
+    // the PC/SC status, if any, is kept to diagnose flaky hardware
+    Connection(Option<pcsc::Error>),
+    Busy,
+    Transmission(Option<pcsc::Error>),
+    NoMatchingSerial,
+    Removed,
+    NoDeviceForTransport,
+    SlotNotConfigured,
+    AppletNotFound,
+    ChallengeTooLong,
+}
+
+/// One of the two slots of the OTP application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    One,
+    Two,
+}
+
+impl Slot {
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            1 => Some(Slot::One),
+            2 => Some(Slot::Two),
+            _ => None,
+        }
+    }
+
+    fn hmac_command(self) -> u8 {
+        match self {
+            Slot::One => 0x30,
+            Slot::Two => 0x38,
+        }
+    }
+}
+
+/// The kind of reader a YubiKey is looked for in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Usb,
+    Nfc,
+    #[default]
+    Any,
+}
+
+impl Transport {
+    fn of_reader(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.contains(YUBIKEY_NAME_FILTER) {
+            Some(Transport::Usb)
+        } else if NFC_NAME_FILTERS.iter().any(|filter| name.contains(filter)) {
+            Some(Transport::Nfc)
+        } else {
+            None
+        }
+    }
+
+    fn accepts(self, transport: Transport) -> bool {
+        self == Transport::Any || self == transport
+    }
+
+    fn no_device_error(self) -> Error {
+        match self {
+            Transport::Any => Error::NoDevice,
+            _ => Error::NoDeviceForTransport,
+        }
+    }
+}
+
+impl Error {
+    pub fn status(&self) -> Option<pcsc::Error> {
+        match self {
+            Error::Connection(status) | Error::Transmission(status) => *status,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Version(pub u8, pub u8, pub u8);
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub serial: u32,
+    pub version: Version,
 }
 
 pub trait SmartCard {
     fn send_and_receive(&self, apdu: &[u8]) -> Result<Vec<u8>, Error>;
+
+    // the answer to selecting the OATH application on connecting, empty for a card connected otherwise
+    fn select_response(&self) -> &[u8] {
+        &[]
+    }
+
+    // serial and firmware version are only available from the OTP application,
+    // which stays selected afterwards
+    fn device_info(&self) -> Result<DeviceInfo, Error> {
+        let version = match self.send_and_receive(APDU_SELECT_OTP)?[..] {
+            [major, minor, patch, .., 0x90, 0x00] => Version(major, minor, patch),
+            _ => return Err(Error::Transmission(None)),
+        };
+        let serial = match self.send_and_receive(APDU_GET_SERIAL)?[..] {
+            [a, b, c, d, 0x90, 0x00] => u32::from_be_bytes([a, b, c, d]),
+            _ => return Err(Error::Transmission(None)),
+        };
+
+        Ok(DeviceInfo { serial, version })
+    }
+
+    // a slot without an HMAC-SHA1 secret answers with an error status or without a response
+    fn challenge_response(&self, slot: Slot, challenge: &[u8]) -> Result<Vec<u8>, Error> {
+        if challenge.len() > MAX_CHALLENGE_LENGTH {
+            return Err(Error::ChallengeTooLong);
+        }
+        self.send_and_receive(APDU_SELECT_OTP)?;
+        let apdu = [
+            &[
+                0x00,
+                INS_OTP,
+                slot.hmac_command(),
+                0x00,
+                challenge.len() as u8,
+            ],
+            challenge,
+        ]
+        .concat();
+        let rapdu = self.send_and_receive(&apdu)?;
+
+        match rapdu.split_last_chunk() {
+            Some((response, [0x90, 0x00])) if response.len() == HMAC_RESPONSE_LENGTH => {
+                Ok(response.to_vec())
+            }
+            _ => Err(Error::SlotNotConfigured),
+        }
+    }
 }
 
 pub struct Yubikey {
     card: Card,
+    select_response: Vec<u8>,
 }
 
 impl SmartCard for Yubikey {
     fn send_and_receive(&self, apdu: &[u8]) -> Result<Vec<u8>, Error> {
         send_and_receive(&self.card, apdu)
     }
+
+    fn select_response(&self) -> &[u8] {
+        &self.select_response
+    }
+}
+
+impl SmartCard for Card {
+    fn send_and_receive(&self, apdu: &[u8]) -> Result<Vec<u8>, Error> {
+        send_and_receive(self, apdu)
+    }
 }
 
 impl Yubikey {
-    pub fn initialize() -> Result<Self, Error> {
-        let card = connect()?;
-        send_and_receive(&card, APDU_SELECT)?;
-        Ok(Self { card })
+    pub fn initialize(transport: Transport, aid: &[u8]) -> Result<Self, Error> {
+        reconnect_once(|| Self::open(transport, aid))
     }
+
+    pub fn initialize_by_serial(
+        serial: u32,
+        transport: Transport,
+        aid: &[u8],
+    ) -> Result<Self, Error> {
+        reconnect_once(|| Self::open_by_serial(serial, transport, aid))
+    }
+
+    fn open(transport: Transport, aid: &[u8]) -> Result<Self, Error> {
+        let ctx = establish()?;
+        let readers = list_readers(&ctx, transport)?;
+        let cards = readers
+            .iter()
+            .map(|reader| with_retry(CONNECT_ATTEMPTS, CONNECT_BACKOFF, || connect(&ctx, reader)));
+
+        Self::select(single_card(cards, transport)?, aid)
+    }
+
+    fn open_by_serial(serial: u32, transport: Transport, aid: &[u8]) -> Result<Self, Error> {
+        let ctx = establish()?;
+        let cards = list_readers(&ctx, transport)?
+            .into_iter()
+            .map(|reader| with_retry(CONNECT_ATTEMPTS, CONNECT_BACKOFF, || connect(&ctx, &reader)));
+
+        Self::select(find_by_serial(cards, serial)?, aid)
+    }
+
+    fn select(card: Card, aid: &[u8]) -> Result<Self, Error> {
+        let select_response = select_applet(&card, aid)?;
+        Ok(Self {
+            card,
+            select_response,
+        })
+    }
+}
+
+// a card without an application of that ID answers with an error status instead
+pub fn select_applet(card: &impl SmartCard, aid: &[u8]) -> Result<Vec<u8>, Error> {
+    let apdu = [APDU_SELECT, &[aid.len() as u8], aid].concat();
+    let response = card.send_and_receive(&apdu)?;
+    if !response.ends_with(b"\x90\x00") {
+        return Err(Error::AppletNotFound);
+    }
+    Ok(response)
+}
+
+pub fn list_devices() -> Result<Vec<DeviceInfo>, Error> {
+    let ctx = establish()?;
+    let cards = list_readers(&ctx, Transport::Any)?
+        .into_iter()
+        .map(|reader| connect(&ctx, &reader));
+
+    Ok(collect_devices(cards))
 }
 
-fn connect() -> Result<Card, Error> {
-    let ctx = Context::establish(Scope::User).map_err(|_| Error::Connection)?;
+// readers that cannot be opened or queried are skipped, e.g. when currently in use
+fn collect_devices<C: SmartCard>(
+    cards: impl IntoIterator<Item = Result<C, Error>>,
+) -> Vec<DeviceInfo> {
+    cards
+        .into_iter()
+        .filter_map(|card| card.and_then(|c| c.device_info()).ok())
+        .collect()
+}
 
-    let readers_buf_len = ctx.list_readers_len().map_err(|_| Error::Connection)?;
+// only a connected card counts, neither an NFC reader without a YubiKey on it nor one failing to
+// connect is another device; the first failure is reported if no card connects at all
+fn single_card<C>(
+    cards: impl IntoIterator<Item = Result<C, Error>>,
+    transport: Transport,
+) -> Result<C, Error> {
+    let mut connected = None;
+    let mut failure = None;
+    for card in cards {
+        match card {
+            Ok(_) if connected.is_some() => return Err(Error::MoreThanOneYubikey),
+            Ok(card) => connected = Some(card),
+            Err(Error::NoDevice) => {}
+            Err(e) => {
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    match (connected, failure) {
+        (Some(card), _) => Ok(card),
+        (None, Some(e)) => Err(e),
+        (None, None) => Err(transport.no_device_error()),
+    }
+}
+
+fn find_by_serial<C: SmartCard>(
+    cards: impl IntoIterator<Item = Result<C, Error>>,
+    serial: u32,
+) -> Result<C, Error> {
+    cards
+        .into_iter()
+        .filter_map(Result::ok)
+        .find(|card| matches!(card.device_info(), Ok(info) if info.serial == serial))
+        .ok_or(Error::NoMatchingSerial)
+}
+
+// the reader may be locked by another process for a moment, so only a busy reader
+// is retried, doubling the delay after every attempt
+fn with_retry<T>(
+    attempts: u32,
+    delay: Duration,
+    mut connect: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut delay = delay;
+    for _ in 1..attempts {
+        match connect() {
+            Err(Error::Busy) => thread::sleep(delay),
+            result => return result,
+        }
+        delay *= 2;
+    }
+    connect()
+}
+
+// a YubiKey replugged between requests leaves PC/SC reporting the old card as removed
+// or reset, which a fresh connection gets over
+fn reconnect_once<T>(mut connect: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    match connect() {
+        Err(Error::Removed) => connect(),
+        result => result,
+    }
+}
+
+fn establish() -> Result<Context, Error> {
+    Context::establish(Scope::User).map_err(|e| Error::Connection(Some(e)))
+}
+
+fn list_readers(ctx: &Context, transport: Transport) -> Result<Vec<CString>, Error> {
+    let readers_buf_len = ctx.list_readers_len().map_err(reader_error)?;
     if readers_buf_len > 4096 {
-        return Err(Error::Connection);
+        return Err(Error::Connection(None));
     }
     let mut readers_buf = vec![0; readers_buf_len];
 
-    let readers = ctx
-        .list_readers(&mut readers_buf)
-        .map_err(|_| Error::Connection)?;
-    let filtered_readers = readers
-        .filter(|r| match r.to_str() {
-            Ok(name) => name
-                .to_lowercase()
-                .contains(&YUBIKEY_NAME_FILTER.to_lowercase()),
-            Err(_) => false,
+    let readers = ctx.list_readers(&mut readers_buf).map_err(reader_error)?;
+    Ok(filter_readers(readers, transport))
+}
+
+fn filter_readers<'a>(
+    readers: impl Iterator<Item = &'a CStr>,
+    transport: Transport,
+) -> Vec<CString> {
+    readers
+        .filter(|r| {
+            r.to_str()
+                .ok()
+                .and_then(Transport::of_reader)
+                .is_some_and(|t| transport.accepts(t))
         })
-        .collect::<Vec<&CStr>>();
+        .map(CStr::to_owned)
+        .collect()
+}
 
-    let reader = match filtered_readers.len() {
-        0 => Err(Error::NoYubikey),
-        1 => Ok(filtered_readers[0]),
-        _ => Err(Error::MoreThanOneYubikey),
-    }?;
+// without any YubiKey plugged in, there usually is no reader at all
+fn reader_error(error: pcsc::Error) -> Error {
+    match error {
+        pcsc::Error::NoReadersAvailable => Error::NoDevice,
+        e => Error::Connection(Some(e)),
+    }
+}
 
+fn connect(ctx: &Context, reader: &CStr) -> Result<Card, Error> {
     let card = ctx
         .connect(reader, ShareMode::Shared, Protocols::ANY)
-        .map_err(|e| match e {
-            pcsc::Error::NoSmartcard => Error::NoYubikey,
-            _ => Error::Connection,
-        })?;
+        .map_err(connect_error)?;
 
     Ok(card)
 }
 
+fn connect_error(error: pcsc::Error) -> Error {
+    match error {
+        pcsc::Error::NoSmartcard => Error::NoDevice,
+        pcsc::Error::SharingViolation => Error::Busy,
+        pcsc::Error::RemovedCard | pcsc::Error::ResetCard => Error::Removed,
+        e => Error::Connection(Some(e)),
+    }
+}
+
+fn transmit_error(error: pcsc::Error) -> Error {
+    match error {
+        pcsc::Error::RemovedCard | pcsc::Error::ResetCard => Error::Removed,
+        e => Error::Transmission(Some(e)),
+    }
+}
+
 fn send_and_receive(card: &Card, apdu: &[u8]) -> Result<std::vec::Vec<u8>, Error> {
     let mut rapdu_buf = [0; MAX_BUFFER_SIZE];
     let rapdu = card
         .transmit(apdu, &mut rapdu_buf)
-        .map_err(|_| Error::Transmission)?;
+        .map_err(transmit_error)?;
     Ok(rapdu.to_vec())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockCard;
+    use super::*;
+    use std::cell::Cell;
+    use test_case::test_case;
+
+    #[test]
+    fn device_info_reads_serial_and_version() {
+        let yubikey = MockCard::new(&[
+            b"\x05\x04\x03\x07\x00\x00\x90\x00",
+            b"\x00\xbc\x61\x4e\x90\x00",
+        ]);
+
+        assert_eq!(
+            DeviceInfo {
+                serial: 12345678,
+                version: Version(5, 4, 3)
+            },
+            yubikey.device_info().unwrap(),
+            "assert device info is read from the OTP application"
+        )
+    }
+
+    #[test]
+    fn challenge_response_refuses_long_challenge() {
+        let yubikey = MockCard::new(&[]);
+
+        let result = yubikey.challenge_response(Slot::One, &[0; MAX_CHALLENGE_LENGTH + 1]);
+
+        assert!(
+            matches!(result, Err(Error::ChallengeTooLong)),
+            "assert over-long challenge is refused as such"
+        );
+        assert!(yubikey.sent().is_empty(), "assert nothing is sent")
+    }
+
+    #[test]
+    fn challenge_response_sends_challenge_to_slot() {
+        let yubikey = MockCard::new(&[
+            b"\x05\x04\x03\x07\x00\x00\x90\x00",
+            &[[0xab; HMAC_RESPONSE_LENGTH].as_slice(), b"\x90\x00"].concat(),
+        ]);
+
+        let response = yubikey.challenge_response(Slot::Two, b"\x01\x02").unwrap();
+
+        assert_eq!(
+            vec![0xab; HMAC_RESPONSE_LENGTH],
+            response,
+            "assert HMAC is read from the response"
+        );
+        assert_eq!(
+            b"\x00\x01\x38\x00\x02\x01\x02".as_slice(),
+            yubikey.sent()[1],
+            "assert challenge is sent to the HMAC command of the slot"
+        )
+    }
+
+    #[test_case(b"\x6a\x80"; "fails on error status")]
+    #[test_case(b"\x90\x00"; "fails on empty response")]
+    fn challenge_response_detects_unconfigured_slot(rapdu: &[u8]) {
+        let yubikey = MockCard::new(&[b"\x05\x04\x03\x07\x00\x00\x90\x00", rapdu]);
+
+        assert!(
+            matches!(
+                yubikey.challenge_response(Slot::One, b"\x01"),
+                Err(Error::SlotNotConfigured)
+            ),
+            "assert slot without HMAC secret is reported"
+        )
+    }
+
+    #[test]
+    fn select_applet_sends_given_aid() {
+        let card = MockCard::new(&[b"\x79\x03\x05\x04\x03\x90\x00"]);
+
+        select_applet(&card, b"\xd2\x76\x00\x01\x24\x01").unwrap();
+
+        assert_eq!(
+            b"\x00\xa4\x04\x00\x06\xd2\x76\x00\x01\x24\x01".as_slice(),
+            card.sent()[0],
+            "assert the application is selected by the given ID"
+        )
+    }
+
+    #[test]
+    fn select_applet_reports_missing_application() {
+        let card = MockCard::new(&[b"\x6a\x82"]);
+
+        assert!(
+            matches!(select_applet(&card, OATH_AID), Err(Error::AppletNotFound)),
+            "assert unknown application ID is reported"
+        )
+    }
+
+    #[test]
+    fn device_info_fails_without_serial() {
+        let yubikey = MockCard::new(&[b"\x05\x04\x03\x07\x00\x00\x90\x00", b"\x6d\x00"]);
+
+        assert!(
+            matches!(yubikey.device_info(), Err(Error::Transmission(None))),
+            "assert missing serial is reported"
+        )
+    }
+
+    fn device_with_serial(serial: u32) -> MockCard {
+        let serial_response = [&serial.to_be_bytes()[..], b"\x90\x00"].concat();
+        MockCard::new(&[
+            b"\x05\x04\x03\x07\x00\x00\x90\x00",
+            &serial_response,
+            &serial_response,
+        ])
+    }
+
+    #[test]
+    fn find_by_serial_selects_matching_device() {
+        let cards = vec![Ok(device_with_serial(1)), Ok(device_with_serial(2))];
+
+        let card = find_by_serial(cards, 2).unwrap();
+
+        assert_eq!(
+            b"\x00\x00\x00\x02\x90\x00".as_slice(),
+            card.send_and_receive(APDU_GET_SERIAL).unwrap(),
+            "assert device with requested serial is selected"
+        )
+    }
+
+    #[test]
+    fn find_by_serial_skips_failing_reader() {
+        let cards = vec![Err(Error::Connection(None)), Ok(device_with_serial(2))];
+
+        assert!(
+            find_by_serial(cards, 2).is_ok(),
+            "assert device behind failing reader is found"
+        )
+    }
+
+    #[test]
+    fn collect_devices_skips_failing_readers() {
+        let cards = vec![
+            Ok(device_with_serial(1)),
+            Err(Error::Connection(None)),
+            Ok(MockCard::new(&[b"\x6a\x82"])),
+            Ok(device_with_serial(2)),
+        ];
+
+        let serials = collect_devices(cards)
+            .iter()
+            .map(|info| info.serial)
+            .collect::<Vec<u32>>();
+
+        assert_eq!(
+            vec![1, 2],
+            serials,
+            "assert failing readers do not drop other devices"
+        )
+    }
+
+    #[test]
+    fn reader_error_reports_missing_reader_as_no_device() {
+        assert!(
+            matches!(
+                reader_error(pcsc::Error::NoReadersAvailable),
+                Error::NoDevice
+            ),
+            "assert missing reader is reported as missing device"
+        );
+        assert!(
+            matches!(
+                reader_error(pcsc::Error::NoService),
+                Error::Connection(Some(pcsc::Error::NoService))
+            ),
+            "assert other failures are reported as connection errors"
+        )
+    }
+
+    #[test]
+    fn connect_error_preserves_status() {
+        let error = connect_error(pcsc::Error::UnpoweredCard);
+
+        assert_eq!(
+            Some(pcsc::Error::UnpoweredCard),
+            error.status(),
+            "assert specific PC/SC status is kept"
+        )
+    }
+
+    #[test_case(pcsc::Error::RemovedCard; "classifies removed card")]
+    #[test_case(pcsc::Error::ResetCard; "classifies reset card")]
+    fn connect_and_transmit_errors_detect_removed_card(error: pcsc::Error) {
+        assert!(
+            matches!(
+                (connect_error(error), transmit_error(error)),
+                (Error::Removed, Error::Removed)
+            ),
+            "assert replugged card is reported as removed"
+        )
+    }
+
+    fn failing_connect(
+        failures: Vec<Error>,
+        attempts: &Cell<u32>,
+    ) -> impl FnMut() -> Result<u32, Error> + '_ {
+        let mut failures = failures.into_iter();
+        move || {
+            attempts.set(attempts.get() + 1);
+            match failures.next() {
+                Some(e) => Err(e),
+                None => Ok(attempts.get()),
+            }
+        }
+    }
+
+    #[test]
+    fn with_retry_succeeds_after_busy_reader() {
+        let attempts = Cell::new(0);
+        let connect = failing_connect(vec![Error::Busy, Error::Busy], &attempts);
+
+        assert_eq!(
+            3,
+            with_retry(3, Duration::ZERO, connect).unwrap(),
+            "assert connection succeeds on third attempt"
+        )
+    }
+
+    #[test]
+    fn with_retry_gives_up_after_attempts() {
+        let attempts = Cell::new(0);
+        let connect = failing_connect(vec![Error::Busy, Error::Busy, Error::Busy], &attempts);
+
+        assert!(
+            matches!(with_retry(2, Duration::ZERO, connect), Err(Error::Busy)),
+            "assert busy reader is reported"
+        );
+        assert_eq!(2, attempts.get(), "assert no further attempt is made")
+    }
+
+    #[test]
+    fn with_retry_fails_fast_without_device() {
+        let attempts = Cell::new(0);
+        let connect = failing_connect(vec![Error::NoDevice], &attempts);
+
+        assert!(
+            matches!(with_retry(3, Duration::ZERO, connect), Err(Error::NoDevice)),
+            "assert missing device is reported"
+        );
+        assert_eq!(1, attempts.get(), "assert missing device is not retried")
+    }
+
+    fn reader_list() -> Vec<CString> {
+        [
+            "Yubico YubiKey OTP+FIDO+CCID 00 00",
+            "ACS ACR122U PICC Interface 01 00",
+            "Identiv uTrust 3700 F CL Reader 02 00",
+            "Alcor Micro AU9540 03 00",
+        ]
+        .iter()
+        .map(|name| CString::new(*name).unwrap())
+        .collect()
+    }
+
+    #[test_case(Transport::Usb, &["Yubico YubiKey OTP+FIDO+CCID 00 00"]; "selects usb reader")]
+    #[test_case(Transport::Nfc, &["ACS ACR122U PICC Interface 01 00", "Identiv uTrust 3700 F CL Reader 02 00"]; "selects nfc readers")]
+    #[test_case(Transport::Any, &["Yubico YubiKey OTP+FIDO+CCID 00 00", "ACS ACR122U PICC Interface 01 00", "Identiv uTrust 3700 F CL Reader 02 00"]; "selects all yubikey readers")]
+    fn filter_readers_by_transport(transport: Transport, expected: &[&str]) {
+        let readers = reader_list();
+
+        let names: Vec<String> = filter_readers(readers.iter().map(CString::as_c_str), transport)
+            .into_iter()
+            .map(|name| name.into_string().unwrap())
+            .collect();
+
+        assert_eq!(
+            expected, names,
+            "assert only readers of the transport are used"
+        )
+    }
+
+    #[test]
+    fn single_card_skips_empty_nfc_reader() {
+        let cards = vec![Ok(device_with_serial(1)), Err(Error::NoDevice)];
+
+        assert!(
+            single_card(cards, Transport::Any).is_ok(),
+            "assert reader without card does not count as another device"
+        )
+    }
+
+    #[test_case(Transport::Nfc, Error::NoDeviceForTransport; "reports missing device of preferred transport")]
+    #[test_case(Transport::Any, Error::NoDevice; "reports missing device")]
+    fn single_card_fails_without_card(transport: Transport, expected: Error) {
+        let cards: Vec<Result<MockCard, Error>> = vec![Err(Error::NoDevice)];
+
+        let error = single_card(cards, transport).err().unwrap();
+
+        assert_eq!(
+            format!("{:?}", expected),
+            format!("{:?}", error),
+            "assert missing device is reported"
+        )
+    }
+
+    #[test_case(vec![Ok(device_with_serial(1)), Ok(device_with_serial(2))], true; "fails on several cards")]
+    #[test_case(vec![Ok(device_with_serial(1)), Err(Error::Busy)], false; "ignores busy reader")]
+    #[test_case(vec![Err(Error::Connection(None)), Ok(device_with_serial(1))], false; "ignores failing reader")]
+    fn single_card_fails_on_several_cards(cards: Vec<Result<MockCard, Error>>, several: bool) {
+        assert_eq!(
+            several,
+            matches!(
+                single_card(cards, Transport::Any),
+                Err(Error::MoreThanOneYubikey)
+            ),
+            "assert only connected cards count as several devices"
+        )
+    }
+
+    #[test]
+    fn single_card_reports_first_failure_without_card() {
+        let cards: Vec<Result<MockCard, Error>> = vec![
+            Err(Error::NoDevice),
+            Err(Error::Busy),
+            Err(Error::Connection(None)),
+        ];
+
+        assert!(
+            matches!(single_card(cards, Transport::Any), Err(Error::Busy)),
+            "assert failure of a reader is reported instead of a missing device"
+        )
+    }
+
+    #[test]
+    fn reconnect_once_succeeds_after_removed_card() {
+        let attempts = Cell::new(0);
+        let connect = failing_connect(vec![Error::Removed], &attempts);
+
+        assert_eq!(
+            2,
+            reconnect_once(connect).unwrap(),
+            "assert removed card is connected again"
+        )
+    }
+
+    #[test]
+    fn reconnect_once_gives_up_after_second_removal() {
+        let attempts = Cell::new(0);
+        let connect = failing_connect(vec![Error::Removed, Error::Removed], &attempts);
+
+        assert!(
+            matches!(reconnect_once(connect), Err(Error::Removed)),
+            "assert card removed again is reported"
+        );
+        assert_eq!(
+            2,
+            attempts.get(),
+            "assert only a single reconnect is attempted"
+        )
+    }
+
+    #[test]
+    fn reconnect_once_does_not_retry_other_failures() {
+        let attempts = Cell::new(0);
+        let connect = failing_connect(vec![Error::NoDevice], &attempts);
+
+        assert!(
+            matches!(reconnect_once(connect), Err(Error::NoDevice)),
+            "assert missing device is reported"
+        );
+        assert_eq!(
+            1,
+            attempts.get(),
+            "assert missing device is not reconnected"
+        )
+    }
+
+    #[test]
+    fn find_by_serial_fails_without_matching_device() {
+        let cards = vec![Ok(device_with_serial(1)), Ok(device_with_serial(2))];
+
+        assert!(
+            matches!(find_by_serial(cards, 3), Err(Error::NoMatchingSerial)),
+            "assert no other device is used"
+        )
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{Error, SmartCard};
+
+    pub struct MockCard {
+        responses: RefCell<VecDeque<Vec<u8>>>,
+        sent: RefCell<Vec<Vec<u8>>>,
+        delay: Duration,
+    }
+
+    impl MockCard {
+        pub fn new(responses: &[&[u8]]) -> Self {
+            Self::with_delay(responses, Duration::ZERO)
+        }
+
+        pub fn with_delay(responses: &[&[u8]], delay: Duration) -> Self {
+            Self {
+                responses: RefCell::new(responses.iter().map(|r| r.to_vec()).collect()),
+                sent: RefCell::new(Vec::new()),
+                delay,
+            }
+        }
+
+        pub fn sent(&self) -> Vec<Vec<u8>> {
+            self.sent.borrow().clone()
+        }
+    }
+
+    impl SmartCard for MockCard {
+        fn send_and_receive(&self, apdu: &[u8]) -> Result<Vec<u8>, Error> {
+            self.sent.borrow_mut().push(apdu.to_vec());
+            thread::sleep(self.delay);
+            self.responses
+                .borrow_mut()
+                .pop_front()
+                .ok_or(Error::Transmission(None))
+        }
+    }
+}